@@ -0,0 +1,481 @@
+use crate::acp::config::TerminalLimits;
+use crate::recording::{Recorder, RecordingSlot};
+use crate::terminal::{open_pty, OutputBuffer, SpawnedPty, Utf8Decoder};
+use agent_client_protocol::TerminalExitStatus;
+use portable_pty::{ChildKiller, CommandBuilder, MasterPty, PtySize};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// How long the exit watcher waits, after the command exits, for the reader to drain what is
+/// left in the PTY. Background processes can hold the PTY open indefinitely, so this is
+/// bounded rather than waiting for EOF.
+const DRAIN_GRACE: Duration = Duration::from_millis(200);
+
+/// A command an agent started with `terminal/create`, running in its own PTY.
+///
+/// Two threads own the blocking side: a reader that drains the PTY into an `OutputBuffer`,
+/// and a single exit watcher that waits on the child and publishes its status on a watch
+/// channel. Everything else only takes short locks, so output polls, waits and kills never
+/// block each other.
+///
+/// portable-pty starts the command in a new session, so on Unix it leads its own process
+/// group and a kill takes down everything it started, not just the direct child.
+pub struct AgentTerminal {
+    output: Arc<Mutex<OutputBuffer>>,
+    recording: Arc<RecordingSlot>,
+    master: Mutex<Box<dyn MasterPty + Send>>,
+    /// Input from the user; agents have no way to write to a terminal in ACP.
+    writer: Mutex<Box<dyn Write + Send>>,
+    killer: Mutex<Box<dyn ChildKiller + Send + Sync>>,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pid: Option<u32>,
+    lifecycle: Arc<Lifecycle>,
+    exit: watch::Receiver<Option<TerminalExitStatus>>,
+}
+
+/// How an agent terminal's command ended, as reported to `on_exit`.
+pub struct TerminalExit {
+    pub status: TerminalExitStatus,
+    pub timed_out: bool,
+}
+
+#[derive(Default)]
+struct Lifecycle {
+    killed: AtomicBool,
+    timed_out: AtomicBool,
+    /// Cleared when the reader hits EOF: until then some process in the group still holds
+    /// the PTY, even after the command itself has exited.
+    pty_open: AtomicBool,
+}
+
+impl AgentTerminal {
+    /// Starts `command` in a PTY of `size`, killing it after `timeout` if given. `on_output`
+    /// sees each decoded chunk as it is read; `on_exit` runs once, after the output has been
+    /// drained. With a `recorder`, output is recorded from the first byte.
+    pub fn spawn(
+        command: CommandBuilder,
+        size: PtySize,
+        output_limit: Option<u64>,
+        timeout: Option<Duration>,
+        recorder: Option<Recorder>,
+        on_output: impl Fn(&str) + Send + 'static,
+        on_exit: impl FnOnce(&TerminalExit) + Send + 'static,
+    ) -> Result<Arc<Self>, String> {
+        let SpawnedPty {
+            master,
+            reader,
+            mut child,
+        } = open_pty(command, size)?;
+        let writer = master
+            .take_writer()
+            .map_err(|err| format!("writer failed: {err}"))?;
+        let killer = child.clone_killer();
+        let pid = child.process_id();
+        let lifecycle = Arc::new(Lifecycle {
+            pty_open: AtomicBool::new(true),
+            ..Lifecycle::default()
+        });
+
+        let output = Arc::new(Mutex::new(OutputBuffer::new(
+            output_limit.map(|limit| limit as usize),
+        )));
+        let recording = Arc::new(RecordingSlot::default());
+        if let Some(recorder) = recorder {
+            recording.start(recorder)?;
+        }
+        let (drained_tx, drained_rx) = mpsc::channel::<()>();
+        let reader_output = output.clone();
+        let reader_recording = recording.clone();
+        let reader_lifecycle = lifecycle.clone();
+        std::thread::spawn(move || {
+            read_output(reader, &reader_output, &reader_recording, on_output);
+            reader_lifecycle.pty_open.store(false, Ordering::SeqCst);
+            drop(drained_tx);
+        });
+
+        let (exit_tx, exit_rx) = watch::channel(None);
+        let (exited_tx, exited_rx) = mpsc::channel::<()>();
+        let watcher_lifecycle = lifecycle.clone();
+        std::thread::spawn(move || {
+            // portable-pty reports a signal death as exit code 1 without the signal, so
+            // commands we killed are reported as killed rather than by their exit code.
+            let status = match child.wait() {
+                Ok(status)
+                    if watcher_lifecycle.killed.load(Ordering::SeqCst) && !status.success() =>
+                {
+                    TerminalExitStatus::new().signal(Some("SIGKILL".to_string()))
+                }
+                Ok(status) => TerminalExitStatus::new().exit_code(Some(status.exit_code())),
+                Err(_) => TerminalExitStatus::new(),
+            };
+            drop(exited_tx);
+            let _ = drained_rx.recv_timeout(DRAIN_GRACE);
+            exit_tx.send_replace(Some(status.clone()));
+            on_exit(&TerminalExit {
+                status,
+                timed_out: watcher_lifecycle.timed_out.load(Ordering::SeqCst),
+            });
+        });
+
+        let terminal = Arc::new(Self {
+            output,
+            recording,
+            master: Mutex::new(master),
+            writer: Mutex::new(writer),
+            killer: Mutex::new(killer),
+            pid,
+            lifecycle,
+            exit: exit_rx,
+        });
+        if let Some(timeout) = timeout {
+            kill_after(Arc::downgrade(&terminal), timeout, exited_rx);
+        }
+        Ok(terminal)
+    }
+
+    /// Buffered output and whether the start of it was dropped to stay under the limit.
+    pub fn output(&self) -> (String, bool) {
+        match self.output.lock() {
+            Ok(output) => (output.text(), output.truncated()),
+            Err(_) => (String::new(), false),
+        }
+    }
+
+    pub fn exit_status(&self) -> Option<TerminalExitStatus> {
+        self.exit.borrow().clone()
+    }
+
+    pub async fn wait(&self) -> Result<TerminalExitStatus, String> {
+        let mut exit = self.exit.clone();
+        let status = exit
+            .wait_for(Option::is_some)
+            .await
+            .map_err(|_| "terminal exit watcher stopped".to_string())?;
+        Ok(status.clone().unwrap_or_default())
+    }
+
+    /// Kills the command and, on Unix, every process in its group. The group is signalled
+    /// after the command exits only while something still holds the PTY, since the group id
+    /// cannot be reused while any of its members is alive. A reaped pid is never signalled.
+    pub fn kill(&self) {
+        let exited = self.exit_status().is_some();
+        if !exited {
+            self.lifecycle.killed.store(true, Ordering::SeqCst);
+        }
+        #[cfg(unix)]
+        if let Some(pid) = self.pid {
+            if !exited || self.lifecycle.pty_open.load(Ordering::SeqCst) {
+                // SAFETY: killpg only sends a signal; an empty group yields ESRCH.
+                unsafe {
+                    libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+                }
+            }
+            return;
+        }
+        if exited {
+            return;
+        }
+        if let Ok(mut killer) = self.killer.lock() {
+            let _ = killer.kill();
+        }
+    }
+
+    pub fn write_input(&self, data: &[u8]) -> Result<(), String> {
+        let mut writer = self
+            .writer
+            .lock()
+            .map_err(|_| "terminal writer poisoned".to_string())?;
+        writer
+            .write_all(data)
+            .and_then(|()| writer.flush())
+            .map_err(|err| format!("write failed: {err}"))
+    }
+
+    pub fn resize(&self, size: PtySize) -> Result<(), String> {
+        self.master
+            .lock()
+            .map_err(|_| "terminal pty poisoned".to_string())?
+            .resize(size)
+            .map_err(|err| format!("resize failed: {err}"))?;
+        self.recording.resize(size.cols, size.rows);
+        Ok(())
+    }
+
+    /// Ends the terminal's recording, if it has one, and returns the file.
+    pub fn stop_recording(&self) -> Option<PathBuf> {
+        self.recording.stop()
+    }
+}
+
+/// Builds the command for an agent terminal. On Unix, CPU and memory limits are set by a
+/// `sh` wrapper that applies `ulimit` and then execs the real program, so the limits cover
+/// it and everything it starts; if a limit cannot be set the command does not run.
+pub fn limited_command(program: &str, args: &[String], limits: &TerminalLimits) -> CommandBuilder {
+    let mut ulimits = Vec::new();
+    if let Some(cpu_secs) = limits.cpu_secs {
+        ulimits.push(format!("ulimit -t {cpu_secs}"));
+    }
+    if let Some(memory_mb) = limits.memory_mb {
+        ulimits.push(format!("ulimit -v {}", memory_mb.saturating_mul(1024)));
+    }
+    if cfg!(not(unix)) || ulimits.is_empty() {
+        let mut command = CommandBuilder::new(program);
+        command.args(args);
+        return command;
+    }
+
+    ulimits.push(r#"exec "$0" "$@""#.to_string());
+    let mut command = CommandBuilder::new("/bin/sh");
+    command.arg("-c");
+    command.arg(ulimits.join(" && "));
+    command.arg(program);
+    command.args(args);
+    command
+}
+
+/// Kills the terminal unless the exit watcher reports the command exited, by closing
+/// `exited`, within `timeout`. Holds only a weak reference, so a released terminal is not
+/// kept alive by its timer.
+fn kill_after(terminal: Weak<AgentTerminal>, timeout: Duration, exited: mpsc::Receiver<()>) {
+    std::thread::spawn(move || {
+        if exited.recv_timeout(timeout) != Err(mpsc::RecvTimeoutError::Timeout) {
+            return;
+        }
+        if let Some(terminal) = terminal.upgrade() {
+            terminal.lifecycle.timed_out.store(true, Ordering::SeqCst);
+            terminal.kill();
+        }
+    });
+}
+
+fn read_output(
+    mut reader: Box<dyn Read + Send>,
+    output: &Mutex<OutputBuffer>,
+    recording: &RecordingSlot,
+    on_output: impl Fn(&str),
+) {
+    let mut decoder = Utf8Decoder::default();
+    let mut buffer = [0u8; 4096];
+    loop {
+        let (text, done) = match reader.read(&mut buffer) {
+            Ok(0) | Err(_) => (decoder.finish(), true),
+            Ok(n) => (decoder.decode(&buffer[..n]), false),
+        };
+        if !text.is_empty() {
+            if let Ok(mut output) = output.lock() {
+                output.push(&text);
+            }
+            recording.output(&text);
+            on_output(&text);
+        }
+        if done {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> CommandBuilder {
+        let mut command = CommandBuilder::new("sh");
+        command.args(["-c", script]);
+        command
+    }
+
+    fn spawn(
+        command: CommandBuilder,
+        output_limit: Option<u64>,
+        timeout: Option<Duration>,
+        on_exit: impl FnOnce(&TerminalExit) + Send + 'static,
+    ) -> Arc<AgentTerminal> {
+        let size = PtySize {
+            rows: 24,
+            cols: 80,
+            pixel_width: 0,
+            pixel_height: 0,
+        };
+        AgentTerminal::spawn(command, size, output_limit, timeout, None, |_| {}, on_exit).unwrap()
+    }
+
+    fn spawn_sh(script: &str, output_limit: Option<u64>) -> Arc<AgentTerminal> {
+        spawn(sh(script), output_limit, None, |_| {})
+    }
+
+    async fn wait(terminal: &AgentTerminal) -> TerminalExitStatus {
+        tokio::time::timeout(Duration::from_secs(10), terminal.wait())
+            .await
+            .expect("terminal did not exit")
+            .unwrap()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn recording_starts_with_the_first_byte() {
+        let root = tempfile::tempdir().unwrap();
+        let recorder = Recorder::create(root.path(), "agent-term-1", "printf", 80, 24).unwrap();
+        let size = PtySize {
+            rows: 24,
+            cols: 80,
+            pixel_width: 0,
+            pixel_height: 0,
+        };
+        let terminal = AgentTerminal::spawn(
+            sh("printf first"),
+            size,
+            None,
+            None,
+            Some(recorder),
+            |_| {},
+            |_| {},
+        )
+        .unwrap();
+        wait(&terminal).await;
+
+        let path = terminal.stop_recording().unwrap();
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let recording = crate::recording::read(root.path(), name).unwrap();
+        let output: String = recording
+            .events
+            .iter()
+            .map(|(_, _, data)| data.as_str())
+            .collect();
+        assert_eq!(output, "first");
+        assert_eq!(terminal.stop_recording(), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stdout_and_stderr_arrive_in_order_with_the_exit_code() {
+        let terminal = spawn_sh("echo one; echo two >&2; echo three; exit 3", None);
+
+        let status = wait(&terminal).await;
+        assert_eq!(status.exit_code, Some(3));
+        assert_eq!(terminal.exit_status(), Some(status));
+        let (output, truncated) = terminal.output();
+        assert_eq!(output, "one\r\ntwo\r\nthree\r\n");
+        assert!(!truncated);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn limited_output_keeps_a_valid_utf8_tail() {
+        let terminal = spawn_sh(
+            "i=0; while [ $i -lt 500 ]; do printf 'é漢'; i=$((i+1)); done",
+            Some(1001),
+        );
+
+        assert_eq!(wait(&terminal).await.exit_code, Some(0));
+        let (output, truncated) = terminal.output();
+        assert!(truncated);
+        assert!(output.len() <= 1001);
+        assert!(output.len() > 990);
+        assert!(output.ends_with("é漢"));
+        assert!(!output.contains(char::REPLACEMENT_CHARACTER));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn kill_wakes_every_waiter_while_output_stays_readable() {
+        let terminal = spawn_sh("echo ready; sleep 30", None);
+        while !terminal.output().0.contains("ready") {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let first = terminal.clone();
+        let second = terminal.clone();
+        let waiters = tokio::spawn(async move { (first.wait().await, second.wait().await) });
+        assert!(terminal.exit_status().is_none());
+        terminal.kill();
+
+        let (first, second) = tokio::time::timeout(Duration::from_secs(10), waiters)
+            .await
+            .expect("waiters did not wake")
+            .unwrap();
+        assert_eq!(first.unwrap(), second.unwrap());
+        let status = terminal.exit_status().unwrap();
+        assert_eq!(status.signal.as_deref(), Some("SIGKILL"));
+        assert_eq!(status.exit_code, None);
+        assert!(terminal.output().0.starts_with("ready"));
+        terminal.kill();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn kill_takes_down_the_whole_process_group() {
+        let terminal = spawn_sh("trap '' HUP; sleep 30 & echo bg=$!; wait", None);
+        let background = loop {
+            let (output, _) = terminal.output();
+            if let Some(pid) = output
+                .split_once("bg=")
+                .and_then(|(_, rest)| rest.split_once("\r\n"))
+            {
+                break pid.0.to_string();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+
+        terminal.kill();
+        wait(&terminal).await;
+        let alive = || {
+            std::fs::read_to_string(format!("/proc/{background}/stat"))
+                .is_ok_and(|stat| !stat.contains(") Z"))
+        };
+        for _ in 0..100 {
+            if !alive() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("background process {background} survived the kill");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn commands_are_killed_after_their_timeout() {
+        let (exit_tx, exit_rx) = mpsc::channel();
+        let terminal = spawn(
+            sh("sleep 30"),
+            None,
+            Some(Duration::from_millis(100)),
+            move |exit| exit_tx.send(exit.timed_out).unwrap(),
+        );
+
+        let status = wait(&terminal).await;
+        assert_eq!(status.signal.as_deref(), Some("SIGKILL"));
+        assert!(exit_rx.recv_timeout(Duration::from_secs(5)).unwrap());
+
+        let (exit_tx, exit_rx) = mpsc::channel();
+        let quick = spawn(
+            sh("exit 0"),
+            None,
+            Some(Duration::from_secs(30)),
+            move |exit| exit_tx.send(exit.timed_out).unwrap(),
+        );
+        assert_eq!(wait(&quick).await.exit_code, Some(0));
+        assert!(!exit_rx.recv_timeout(Duration::from_secs(5)).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn cpu_and_memory_limits_apply_to_the_command() {
+        let limits = TerminalLimits {
+            timeout_secs: None,
+            cpu_secs: Some(7),
+            memory_mb: Some(512),
+        };
+        let args = ["-c".to_string(), "ulimit -t; ulimit -v".to_string()];
+        let terminal = spawn(limited_command("sh", &args, &limits), None, None, |_| {});
+
+        assert_eq!(wait(&terminal).await.exit_code, Some(0));
+        assert_eq!(terminal.output().0, "7\r\n524288\r\n");
+
+        let unlimited = limited_command("sh", &args, &TerminalLimits::default());
+        assert_eq!(unlimited.get_argv()[0], "sh");
+    }
+}
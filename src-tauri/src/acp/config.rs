@@ -9,7 +9,7 @@ pub struct AgentsConfig {
     pub agents: Vec<AgentConfig>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct AgentConfig {
     pub id: String,
     pub label: String,
@@ -18,6 +18,29 @@ pub struct AgentConfig {
     pub args: Vec<String>,
     #[serde(default)]
     pub env: HashMap<String, String>,
+    /// Cap on a single `fs/read_text_file` response; defaults to `DEFAULT_MAX_READ_BYTES`.
+    #[serde(default)]
+    pub max_read_bytes: Option<u64>,
+    /// Directories outside the project root the agent may also read and write.
+    #[serde(default)]
+    pub extra_roots: Vec<PathBuf>,
+    /// Globs, relative to each root, the agent may not touch; added to the built-in
+    /// `.git`, `.acp` and `.env*` denylist.
+    #[serde(default)]
+    pub denied_paths: Vec<String>,
+    #[serde(default)]
+    pub terminal_limits: TerminalLimits,
+}
+
+/// Limits applied to every terminal the agent creates.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct TerminalLimits {
+    /// Wall-clock seconds before the command's whole process group is killed.
+    pub timeout_secs: Option<u64>,
+    /// CPU seconds (`ulimit -t`); Unix only.
+    pub cpu_secs: Option<u64>,
+    /// Virtual memory in MiB (`ulimit -v`); Unix only.
+    pub memory_mb: Option<u64>,
 }
 
 impl AgentsConfig {
@@ -0,0 +1,961 @@
+use agent_client_protocol::{
+    AvailableCommand, AvailableCommandInput, ContentBlock, EmbeddedResourceResource,
+    PermissionOption, PermissionOptionKind, PlanEntry, PlanEntryPriority, PlanEntryStatus,
+    RequestPermissionOutcome, StopReason, ToolCall, ToolCallContent, ToolCallLocation,
+    ToolCallStatus, ToolCallUpdate, ToolKind,
+};
+use serde::Serialize;
+
+/// Events emitted to the webview on `acp://update`.
+///
+/// ACP schema types are mapped into the snake_case `Ui*` shapes below; enums that serialize
+/// as plain strings (tool kinds, statuses, plan priorities, stop reasons) are reused as-is.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AcpUiEvent {
+    AgentMessage {
+        session_id: String,
+        content: UiContent,
+    },
+    AgentThought {
+        session_id: String,
+        content: UiContent,
+    },
+    UserMessage {
+        session_id: String,
+        content: UiContent,
+    },
+    ToolCall {
+        session_id: String,
+        tool_call: UiTrackedToolCall,
+    },
+    /// Carries both the partial update and the merged state of the call it applies to.
+    ToolCallUpdate {
+        session_id: String,
+        update: UiToolCallUpdate,
+        tool_call: UiTrackedToolCall,
+    },
+    /// An agent write that was applied to disk and recorded in the session's change journal.
+    FileChanged {
+        session_id: String,
+        change: UiFileChange,
+    },
+    /// An agent write was staged, or staged changes were accepted or rejected. `file` is the
+    /// remaining staged state for `path`, or `None` once nothing is left to review.
+    Staged {
+        session_id: String,
+        path: String,
+        file: Option<UiStagedFile>,
+    },
+    /// Files a rollback restored or deleted; their journal entries are gone.
+    RolledBack {
+        session_id: String,
+        paths: Vec<String>,
+    },
+    /// An agent started a command in a terminal; its output follows as `TerminalOutput`.
+    TerminalStarted {
+        session_id: String,
+        terminal_id: String,
+        command: String,
+    },
+    /// `terminal/create` was refused by the project's command policy.
+    CommandDenied {
+        session_id: String,
+        command: String,
+        message: String,
+    },
+    /// A chunk of PTY output from an agent terminal, raw escape sequences included.
+    TerminalOutput {
+        session_id: String,
+        terminal_id: String,
+        data: String,
+    },
+    /// An agent terminal's command exited; all of its output has been sent. `signal` is set
+    /// when it was killed, by the user, the agent, or its timeout (`timed_out`).
+    TerminalExited {
+        session_id: String,
+        terminal_id: String,
+        exit_code: Option<u32>,
+        signal: Option<String>,
+        timed_out: bool,
+    },
+    Plan {
+        session_id: String,
+        entries: Vec<UiPlanEntry>,
+    },
+    AvailableCommands {
+        session_id: String,
+        commands: Vec<UiCommand>,
+    },
+    CurrentMode {
+        session_id: String,
+        mode_id: String,
+    },
+    StatusUpdate {
+        session_id: String,
+        content: String,
+    },
+    Error {
+        session_id: String,
+        content: String,
+    },
+    PermissionRequest {
+        session_id: String,
+        request_id: String,
+        tool_call: UiToolCallUpdate,
+        options: Vec<UiPermissionOption>,
+    },
+    PermissionResolved {
+        session_id: String,
+        request_id: String,
+        option_id: Option<String>,
+    },
+    PromptQueued {
+        session_id: String,
+        prompt_id: String,
+        position: usize,
+    },
+    TurnStarted {
+        session_id: String,
+        prompt_id: String,
+    },
+    TurnEnded {
+        session_id: String,
+        prompt_id: String,
+        stop_reason: StopReason,
+    },
+    TurnFailed {
+        session_id: String,
+        prompt_id: String,
+        content: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UiContent {
+    Text {
+        text: String,
+    },
+    Image {
+        mime_type: String,
+        data: String,
+        uri: Option<String>,
+    },
+    Audio {
+        mime_type: String,
+        data: String,
+    },
+    ResourceLink {
+        uri: String,
+        name: String,
+        title: Option<String>,
+        description: Option<String>,
+        mime_type: Option<String>,
+        size: Option<i64>,
+    },
+    Resource {
+        uri: String,
+        mime_type: Option<String>,
+        text: Option<String>,
+        blob: Option<String>,
+    },
+    Unsupported,
+}
+
+impl From<&ContentBlock> for UiContent {
+    fn from(block: &ContentBlock) -> Self {
+        match block {
+            ContentBlock::Text(text) => UiContent::Text {
+                text: text.text.clone(),
+            },
+            ContentBlock::Image(image) => UiContent::Image {
+                mime_type: image.mime_type.clone(),
+                data: image.data.clone(),
+                uri: image.uri.clone(),
+            },
+            ContentBlock::Audio(audio) => UiContent::Audio {
+                mime_type: audio.mime_type.clone(),
+                data: audio.data.clone(),
+            },
+            ContentBlock::ResourceLink(link) => UiContent::ResourceLink {
+                uri: link.uri.clone(),
+                name: link.name.clone(),
+                title: link.title.clone(),
+                description: link.description.clone(),
+                mime_type: link.mime_type.clone(),
+                size: link.size,
+            },
+            ContentBlock::Resource(resource) => match &resource.resource {
+                EmbeddedResourceResource::TextResourceContents(contents) => UiContent::Resource {
+                    uri: contents.uri.clone(),
+                    mime_type: contents.mime_type.clone(),
+                    text: Some(contents.text.clone()),
+                    blob: None,
+                },
+                EmbeddedResourceResource::BlobResourceContents(contents) => UiContent::Resource {
+                    uri: contents.uri.clone(),
+                    mime_type: contents.mime_type.clone(),
+                    text: None,
+                    blob: Some(contents.blob.clone()),
+                },
+                _ => UiContent::Unsupported,
+            },
+            _ => UiContent::Unsupported,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UiToolCallContent {
+    Content {
+        content: UiContent,
+    },
+    Diff {
+        path: String,
+        old_text: Option<String>,
+        new_text: String,
+    },
+    Terminal {
+        terminal_id: String,
+    },
+}
+
+impl From<&ToolCallContent> for UiToolCallContent {
+    fn from(content: &ToolCallContent) -> Self {
+        match content {
+            ToolCallContent::Diff(diff) => UiToolCallContent::Diff {
+                path: diff.path.display().to_string(),
+                old_text: diff.old_text.clone(),
+                new_text: diff.new_text.clone(),
+            },
+            ToolCallContent::Terminal(terminal) => UiToolCallContent::Terminal {
+                terminal_id: terminal.terminal_id.to_string(),
+            },
+            ToolCallContent::Content(content) => UiToolCallContent::Content {
+                content: UiContent::from(&content.content),
+            },
+            _ => UiToolCallContent::Content {
+                content: UiContent::Unsupported,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UiLocation {
+    pub path: String,
+    pub line: Option<u32>,
+}
+
+impl From<&ToolCallLocation> for UiLocation {
+    fn from(location: &ToolCallLocation) -> Self {
+        Self {
+            path: location.path.display().to_string(),
+            line: location.line,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UiToolCall {
+    pub tool_call_id: String,
+    pub title: String,
+    pub kind: ToolKind,
+    pub status: ToolCallStatus,
+    pub content: Vec<UiToolCallContent>,
+    pub locations: Vec<UiLocation>,
+    pub raw_input: Option<serde_json::Value>,
+    pub raw_output: Option<serde_json::Value>,
+}
+
+impl From<&ToolCall> for UiToolCall {
+    fn from(tool_call: &ToolCall) -> Self {
+        Self {
+            tool_call_id: tool_call.tool_call_id.to_string(),
+            title: tool_call.title.clone(),
+            kind: tool_call.kind,
+            status: tool_call.status,
+            content: tool_call
+                .content
+                .iter()
+                .map(UiToolCallContent::from)
+                .collect(),
+            locations: tool_call.locations.iter().map(UiLocation::from).collect(),
+            raw_input: tool_call.raw_input.clone(),
+            raw_output: tool_call.raw_output.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UiDiff {
+    pub path: String,
+    pub old_text: Option<String>,
+    pub new_text: String,
+}
+
+/// A tool call as tracked over its lifetime: the merged current state plus every diff it has
+/// reported (latest per path) and every file it has touched, relative to the session root.
+#[derive(Debug, Clone, Serialize)]
+pub struct UiTrackedToolCall {
+    #[serde(flatten)]
+    pub tool_call: UiToolCall,
+    pub diffs: Vec<UiDiff>,
+    pub touched_paths: Vec<String>,
+}
+
+/// One entry of a session's change journal. `previous_content` is `None` when the write
+/// created the file.
+#[derive(Debug, Clone, Serialize)]
+pub struct UiFileChange {
+    pub change_id: u64,
+    pub path: String,
+    pub previous_content: Option<String>,
+    pub new_content: String,
+    pub tool_call_id: Option<String>,
+    pub prompt_id: Option<String>,
+    pub timestamp_ms: u64,
+}
+
+/// A file with agent writes waiting for review in staged-write mode.
+#[derive(Debug, Clone, Serialize)]
+pub struct UiStagedFile {
+    pub path: String,
+    pub created: bool,
+    pub tool_call_id: Option<String>,
+    pub prompt_id: Option<String>,
+    pub hunks: Vec<UiHunk>,
+}
+
+/// A run of changed lines between disk and the staged content. Line numbers are 1-based;
+/// `index` is the position to pass when accepting or rejecting this hunk.
+#[derive(Debug, Clone, Serialize)]
+pub struct UiHunk {
+    pub index: usize,
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    pub removed: String,
+    pub added: String,
+}
+
+/// The checkpoint taken when a prompt turn started, with the files that turn changed.
+#[derive(Debug, Clone, Serialize)]
+pub struct UiCheckpoint {
+    pub prompt_id: String,
+    pub timestamp_ms: u64,
+    pub changed_paths: Vec<String>,
+}
+
+/// A partial tool call; only the fields the agent changed are set.
+#[derive(Debug, Clone, Serialize)]
+pub struct UiToolCallUpdate {
+    pub tool_call_id: String,
+    pub title: Option<String>,
+    pub kind: Option<ToolKind>,
+    pub status: Option<ToolCallStatus>,
+    pub content: Option<Vec<UiToolCallContent>>,
+    pub locations: Option<Vec<UiLocation>>,
+    pub raw_input: Option<serde_json::Value>,
+    pub raw_output: Option<serde_json::Value>,
+}
+
+impl From<&ToolCallUpdate> for UiToolCallUpdate {
+    fn from(update: &ToolCallUpdate) -> Self {
+        let fields = &update.fields;
+        Self {
+            tool_call_id: update.tool_call_id.to_string(),
+            title: fields.title.clone(),
+            kind: fields.kind,
+            status: fields.status,
+            content: fields
+                .content
+                .as_ref()
+                .map(|content| content.iter().map(UiToolCallContent::from).collect()),
+            locations: fields
+                .locations
+                .as_ref()
+                .map(|locations| locations.iter().map(UiLocation::from).collect()),
+            raw_input: fields.raw_input.clone(),
+            raw_output: fields.raw_output.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UiPlanEntry {
+    pub content: String,
+    pub priority: PlanEntryPriority,
+    pub status: PlanEntryStatus,
+}
+
+impl From<&PlanEntry> for UiPlanEntry {
+    fn from(entry: &PlanEntry) -> Self {
+        Self {
+            content: entry.content.clone(),
+            priority: entry.priority.clone(),
+            status: entry.status.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UiCommand {
+    pub name: String,
+    pub description: String,
+    pub input_hint: Option<String>,
+}
+
+impl From<&AvailableCommand> for UiCommand {
+    fn from(command: &AvailableCommand) -> Self {
+        let input_hint = match &command.input {
+            Some(AvailableCommandInput::Unstructured(input)) => Some(input.hint.clone()),
+            _ => None,
+        };
+        Self {
+            name: command.name.clone(),
+            description: command.description.clone(),
+            input_hint,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UiPermissionOption {
+    pub option_id: String,
+    pub name: String,
+    pub kind: PermissionOptionKind,
+}
+
+impl From<&PermissionOption> for UiPermissionOption {
+    fn from(option: &PermissionOption) -> Self {
+        Self {
+            option_id: option.option_id.to_string(),
+            name: option.name.clone(),
+            kind: option.kind,
+        }
+    }
+}
+
+/// The option id a permission outcome selected, or `None` when it was cancelled.
+pub fn selected_option_id(outcome: &RequestPermissionOutcome) -> Option<String> {
+    match outcome {
+        RequestPermissionOutcome::Selected(selected) => Some(selected.option_id.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn shape(value: &impl Serialize) -> Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    fn text(text: &str) -> UiContent {
+        UiContent::Text {
+            text: text.to_string(),
+        }
+    }
+
+    fn tracked_tool_call() -> UiTrackedToolCall {
+        UiTrackedToolCall {
+            tool_call: UiToolCall {
+                tool_call_id: "call-1".to_string(),
+                title: "Edit main.rs".to_string(),
+                kind: ToolKind::Edit,
+                status: ToolCallStatus::InProgress,
+                content: vec![UiToolCallContent::Terminal {
+                    terminal_id: "term-1".to_string(),
+                }],
+                locations: vec![UiLocation {
+                    path: "src/main.rs".to_string(),
+                    line: Some(3),
+                }],
+                raw_input: Some(json!({ "path": "src/main.rs" })),
+                raw_output: None,
+            },
+            diffs: vec![UiDiff {
+                path: "src/main.rs".to_string(),
+                old_text: None,
+                new_text: "fn main() {}".to_string(),
+            }],
+            touched_paths: vec!["src/main.rs".to_string()],
+        }
+    }
+
+    fn tracked_tool_call_shape() -> Value {
+        json!({
+            "tool_call_id": "call-1",
+            "title": "Edit main.rs",
+            "kind": "edit",
+            "status": "in_progress",
+            "content": [{ "type": "terminal", "terminal_id": "term-1" }],
+            "locations": [{ "path": "src/main.rs", "line": 3 }],
+            "raw_input": { "path": "src/main.rs" },
+            "raw_output": null,
+            "diffs": [{ "path": "src/main.rs", "old_text": null, "new_text": "fn main() {}" }],
+            "touched_paths": ["src/main.rs"],
+        })
+    }
+
+    fn update() -> UiToolCallUpdate {
+        UiToolCallUpdate {
+            tool_call_id: "call-1".to_string(),
+            title: None,
+            kind: None,
+            status: Some(ToolCallStatus::Completed),
+            content: None,
+            locations: None,
+            raw_input: None,
+            raw_output: Some(json!("ok")),
+        }
+    }
+
+    fn update_shape() -> Value {
+        json!({
+            "tool_call_id": "call-1",
+            "title": null,
+            "kind": null,
+            "status": "completed",
+            "content": null,
+            "locations": null,
+            "raw_input": null,
+            "raw_output": "ok",
+        })
+    }
+
+    #[test]
+    fn every_event_serializes_to_the_shape_the_ui_expects() {
+        let session_id = || "s-1".to_string();
+        let cases = vec![
+            (
+                AcpUiEvent::AgentMessage {
+                    session_id: session_id(),
+                    content: text("hi"),
+                },
+                json!({
+                    "type": "agent_message",
+                    "session_id": "s-1",
+                    "content": { "type": "text", "text": "hi" },
+                }),
+            ),
+            (
+                AcpUiEvent::AgentThought {
+                    session_id: session_id(),
+                    content: text("hmm"),
+                },
+                json!({
+                    "type": "agent_thought",
+                    "session_id": "s-1",
+                    "content": { "type": "text", "text": "hmm" },
+                }),
+            ),
+            (
+                AcpUiEvent::UserMessage {
+                    session_id: session_id(),
+                    content: text("fix it"),
+                },
+                json!({
+                    "type": "user_message",
+                    "session_id": "s-1",
+                    "content": { "type": "text", "text": "fix it" },
+                }),
+            ),
+            (
+                AcpUiEvent::ToolCall {
+                    session_id: session_id(),
+                    tool_call: tracked_tool_call(),
+                },
+                json!({
+                    "type": "tool_call",
+                    "session_id": "s-1",
+                    "tool_call": tracked_tool_call_shape(),
+                }),
+            ),
+            (
+                AcpUiEvent::ToolCallUpdate {
+                    session_id: session_id(),
+                    update: update(),
+                    tool_call: tracked_tool_call(),
+                },
+                json!({
+                    "type": "tool_call_update",
+                    "session_id": "s-1",
+                    "update": update_shape(),
+                    "tool_call": tracked_tool_call_shape(),
+                }),
+            ),
+            (
+                AcpUiEvent::FileChanged {
+                    session_id: session_id(),
+                    change: UiFileChange {
+                        change_id: 2,
+                        path: "src/main.rs".to_string(),
+                        previous_content: None,
+                        new_content: "fn main() {}".to_string(),
+                        tool_call_id: Some("call-1".to_string()),
+                        prompt_id: Some("prompt-1".to_string()),
+                        timestamp_ms: 1_700_000_000_000,
+                    },
+                },
+                json!({
+                    "type": "file_changed",
+                    "session_id": "s-1",
+                    "change": {
+                        "change_id": 2,
+                        "path": "src/main.rs",
+                        "previous_content": null,
+                        "new_content": "fn main() {}",
+                        "tool_call_id": "call-1",
+                        "prompt_id": "prompt-1",
+                        "timestamp_ms": 1_700_000_000_000u64,
+                    },
+                }),
+            ),
+            (
+                AcpUiEvent::Staged {
+                    session_id: session_id(),
+                    path: "src/lib.rs".to_string(),
+                    file: Some(UiStagedFile {
+                        path: "src/lib.rs".to_string(),
+                        created: false,
+                        tool_call_id: None,
+                        prompt_id: Some("prompt-1".to_string()),
+                        hunks: vec![UiHunk {
+                            index: 0,
+                            old_start: 1,
+                            old_lines: 1,
+                            new_start: 1,
+                            new_lines: 2,
+                            removed: "a\n".to_string(),
+                            added: "b\nc\n".to_string(),
+                        }],
+                    }),
+                },
+                json!({
+                    "type": "staged",
+                    "session_id": "s-1",
+                    "path": "src/lib.rs",
+                    "file": {
+                        "path": "src/lib.rs",
+                        "created": false,
+                        "tool_call_id": null,
+                        "prompt_id": "prompt-1",
+                        "hunks": [{
+                            "index": 0,
+                            "old_start": 1,
+                            "old_lines": 1,
+                            "new_start": 1,
+                            "new_lines": 2,
+                            "removed": "a\n",
+                            "added": "b\nc\n",
+                        }],
+                    },
+                }),
+            ),
+            (
+                AcpUiEvent::RolledBack {
+                    session_id: session_id(),
+                    paths: vec!["src/lib.rs".to_string()],
+                },
+                json!({ "type": "rolled_back", "session_id": "s-1", "paths": ["src/lib.rs"] }),
+            ),
+            (
+                AcpUiEvent::TerminalStarted {
+                    session_id: session_id(),
+                    terminal_id: "term-1".to_string(),
+                    command: "cargo test".to_string(),
+                },
+                json!({
+                    "type": "terminal_started",
+                    "session_id": "s-1",
+                    "terminal_id": "term-1",
+                    "command": "cargo test",
+                }),
+            ),
+            (
+                AcpUiEvent::CommandDenied {
+                    session_id: session_id(),
+                    command: "rm".to_string(),
+                    message: "rm is denied".to_string(),
+                },
+                json!({
+                    "type": "command_denied",
+                    "session_id": "s-1",
+                    "command": "rm",
+                    "message": "rm is denied",
+                }),
+            ),
+            (
+                AcpUiEvent::TerminalOutput {
+                    session_id: session_id(),
+                    terminal_id: "term-1".to_string(),
+                    data: "\x1b[32mok\x1b[0m\r\n".to_string(),
+                },
+                json!({
+                    "type": "terminal_output",
+                    "session_id": "s-1",
+                    "terminal_id": "term-1",
+                    "data": "\x1b[32mok\x1b[0m\r\n",
+                }),
+            ),
+            (
+                AcpUiEvent::TerminalExited {
+                    session_id: session_id(),
+                    terminal_id: "term-1".to_string(),
+                    exit_code: None,
+                    signal: Some("SIGKILL".to_string()),
+                    timed_out: true,
+                },
+                json!({
+                    "type": "terminal_exited",
+                    "session_id": "s-1",
+                    "terminal_id": "term-1",
+                    "exit_code": null,
+                    "signal": "SIGKILL",
+                    "timed_out": true,
+                }),
+            ),
+            (
+                AcpUiEvent::Plan {
+                    session_id: session_id(),
+                    entries: vec![UiPlanEntry {
+                        content: "Write tests".to_string(),
+                        priority: PlanEntryPriority::High,
+                        status: PlanEntryStatus::InProgress,
+                    }],
+                },
+                json!({
+                    "type": "plan",
+                    "session_id": "s-1",
+                    "entries": [{
+                        "content": "Write tests",
+                        "priority": "high",
+                        "status": "in_progress",
+                    }],
+                }),
+            ),
+            (
+                AcpUiEvent::AvailableCommands {
+                    session_id: session_id(),
+                    commands: vec![UiCommand {
+                        name: "review".to_string(),
+                        description: "Review the diff".to_string(),
+                        input_hint: Some("branch".to_string()),
+                    }],
+                },
+                json!({
+                    "type": "available_commands",
+                    "session_id": "s-1",
+                    "commands": [{
+                        "name": "review",
+                        "description": "Review the diff",
+                        "input_hint": "branch",
+                    }],
+                }),
+            ),
+            (
+                AcpUiEvent::CurrentMode {
+                    session_id: session_id(),
+                    mode_id: "code".to_string(),
+                },
+                json!({ "type": "current_mode", "session_id": "s-1", "mode_id": "code" }),
+            ),
+            (
+                AcpUiEvent::StatusUpdate {
+                    session_id: session_id(),
+                    content: "Allowed by policy: Read".to_string(),
+                },
+                json!({
+                    "type": "status_update",
+                    "session_id": "s-1",
+                    "content": "Allowed by policy: Read",
+                }),
+            ),
+            (
+                AcpUiEvent::Error {
+                    session_id: session_id(),
+                    content: "agent crashed".to_string(),
+                },
+                json!({ "type": "error", "session_id": "s-1", "content": "agent crashed" }),
+            ),
+            (
+                AcpUiEvent::PermissionRequest {
+                    session_id: session_id(),
+                    request_id: "perm-1".to_string(),
+                    tool_call: update(),
+                    options: vec![UiPermissionOption {
+                        option_id: "once".to_string(),
+                        name: "Allow once".to_string(),
+                        kind: PermissionOptionKind::AllowOnce,
+                    }],
+                },
+                json!({
+                    "type": "permission_request",
+                    "session_id": "s-1",
+                    "request_id": "perm-1",
+                    "tool_call": update_shape(),
+                    "options": [{
+                        "option_id": "once",
+                        "name": "Allow once",
+                        "kind": "allow_once",
+                    }],
+                }),
+            ),
+            (
+                AcpUiEvent::PermissionResolved {
+                    session_id: session_id(),
+                    request_id: "perm-1".to_string(),
+                    option_id: None,
+                },
+                json!({
+                    "type": "permission_resolved",
+                    "session_id": "s-1",
+                    "request_id": "perm-1",
+                    "option_id": null,
+                }),
+            ),
+            (
+                AcpUiEvent::PromptQueued {
+                    session_id: session_id(),
+                    prompt_id: "prompt-2".to_string(),
+                    position: 1,
+                },
+                json!({
+                    "type": "prompt_queued",
+                    "session_id": "s-1",
+                    "prompt_id": "prompt-2",
+                    "position": 1,
+                }),
+            ),
+            (
+                AcpUiEvent::TurnStarted {
+                    session_id: session_id(),
+                    prompt_id: "prompt-1".to_string(),
+                },
+                json!({ "type": "turn_started", "session_id": "s-1", "prompt_id": "prompt-1" }),
+            ),
+            (
+                AcpUiEvent::TurnEnded {
+                    session_id: session_id(),
+                    prompt_id: "prompt-1".to_string(),
+                    stop_reason: StopReason::EndTurn,
+                },
+                json!({
+                    "type": "turn_ended",
+                    "session_id": "s-1",
+                    "prompt_id": "prompt-1",
+                    "stop_reason": "end_turn",
+                }),
+            ),
+            (
+                AcpUiEvent::TurnFailed {
+                    session_id: session_id(),
+                    prompt_id: "prompt-1".to_string(),
+                    content: "connection closed".to_string(),
+                },
+                json!({
+                    "type": "turn_failed",
+                    "session_id": "s-1",
+                    "prompt_id": "prompt-1",
+                    "content": "connection closed",
+                }),
+            ),
+        ];
+        for (event, expected) in cases {
+            assert_eq!(shape(&event), expected, "{event:?}");
+        }
+    }
+
+    #[test]
+    fn content_serializes_to_the_shape_the_ui_expects() {
+        let cases = vec![
+            (text("hi"), json!({ "type": "text", "text": "hi" })),
+            (
+                UiContent::Image {
+                    mime_type: "image/png".to_string(),
+                    data: "aGk=".to_string(),
+                    uri: None,
+                },
+                json!({ "type": "image", "mime_type": "image/png", "data": "aGk=", "uri": null }),
+            ),
+            (
+                UiContent::Audio {
+                    mime_type: "audio/wav".to_string(),
+                    data: "aGk=".to_string(),
+                },
+                json!({ "type": "audio", "mime_type": "audio/wav", "data": "aGk=" }),
+            ),
+            (
+                UiContent::ResourceLink {
+                    uri: "file:///src/main.rs".to_string(),
+                    name: "main.rs".to_string(),
+                    title: None,
+                    description: None,
+                    mime_type: Some("text/x-rust".to_string()),
+                    size: Some(12),
+                },
+                json!({
+                    "type": "resource_link",
+                    "uri": "file:///src/main.rs",
+                    "name": "main.rs",
+                    "title": null,
+                    "description": null,
+                    "mime_type": "text/x-rust",
+                    "size": 12,
+                }),
+            ),
+            (
+                UiContent::Resource {
+                    uri: "file:///notes.md".to_string(),
+                    mime_type: None,
+                    text: Some("# Notes".to_string()),
+                    blob: None,
+                },
+                json!({
+                    "type": "resource",
+                    "uri": "file:///notes.md",
+                    "mime_type": null,
+                    "text": "# Notes",
+                    "blob": null,
+                }),
+            ),
+            (UiContent::Unsupported, json!({ "type": "unsupported" })),
+        ];
+        for (content, expected) in cases {
+            assert_eq!(shape(&content), expected, "{content:?}");
+        }
+
+        let cases = vec![
+            (
+                UiToolCallContent::Content {
+                    content: text("done"),
+                },
+                json!({ "type": "content", "content": { "type": "text", "text": "done" } }),
+            ),
+            (
+                UiToolCallContent::Diff {
+                    path: "src/main.rs".to_string(),
+                    old_text: Some("a".to_string()),
+                    new_text: "b".to_string(),
+                },
+                json!({ "type": "diff", "path": "src/main.rs", "old_text": "a", "new_text": "b" }),
+            ),
+            (
+                UiToolCallContent::Terminal {
+                    terminal_id: "term-1".to_string(),
+                },
+                json!({ "type": "terminal", "terminal_id": "term-1" }),
+            ),
+        ];
+        for (content, expected) in cases {
+            assert_eq!(shape(&content), expected, "{content:?}");
+        }
+    }
+}
@@ -1,26 +1,40 @@
+use crate::acp::agent_terminal::{limited_command, AgentTerminal};
+use crate::acp::command_policy::CommandPolicy;
+use crate::acp::config::TerminalLimits;
+use crate::acp::events::{
+    selected_option_id, AcpUiEvent, UiCommand, UiContent, UiFileChange, UiPermissionOption,
+    UiPlanEntry, UiStagedFile, UiToolCallUpdate,
+};
+use crate::acp::journal::{ChangeJournal, RollbackScope};
+use crate::acp::overlay::{StagedOverlay, StagedWrite};
+use crate::acp::permissions::{PermissionBroker, PERMISSION_TIMEOUT};
+use crate::acp::policy::{PermissionPolicy, PolicyDecision};
+use crate::acp::sandbox::Sandbox;
+use crate::acp::text_file::{conform_to_previous, read_text, window_text, write_text};
+use crate::acp::tool_calls::{display_relative, resolve_in_root, ToolCallStore};
+use crate::buffers::{notify_buffer_updated, BufferRegistry};
+use crate::recording::Recorder;
 use agent_client_protocol::{
-    AgentNotification, AgentRequest, ClientCapabilities, ClientResponse, ContentBlock,
-    CreateTerminalRequest, CreateTerminalResponse, FileSystemCapability, KillTerminalCommandResponse,
-    MessageHandler, PermissionOptionKind, ReadTextFileResponse, ReleaseTerminalResponse,
-    RequestPermissionOutcome, RequestPermissionResponse, SelectedPermissionOutcome,
-    SessionNotification, SessionUpdate, TerminalExitStatus, TerminalId, TerminalOutputRequest,
-    TerminalOutputResponse, WaitForTerminalExitRequest, WaitForTerminalExitResponse,
-    WriteTextFileResponse,
+    AgentNotification, AgentRequest, ClientCapabilities, ClientResponse, CreateTerminalRequest,
+    CreateTerminalResponse, FileSystemCapability, KillTerminalCommandResponse, MessageHandler,
+    ReadTextFileResponse, ReleaseTerminalResponse, RequestPermissionOutcome,
+    RequestPermissionResponse, SessionNotification, SessionUpdate, TerminalId,
+    TerminalOutputRequest, TerminalOutputResponse, WaitForTerminalExitRequest,
+    WaitForTerminalExitResponse, WriteTextFileResponse,
 };
 use agent_client_protocol::{
     Error, KillTerminalCommandRequest, ReadTextFileRequest, ReleaseTerminalRequest,
     RequestPermissionRequest, Result, WriteTextFileRequest,
 };
-use serde::Serialize;
+use portable_pty::PtySize;
 use std::collections::HashMap;
-use std::path::{Component, Path, PathBuf};
+use std::path::{Path, PathBuf};
 use std::sync::{
-    atomic::{AtomicUsize, Ordering},
+    atomic::{AtomicBool, AtomicUsize, Ordering},
     Arc,
 };
-use tauri::Emitter;
-use tokio::io::AsyncReadExt;
-use tokio::process::Command;
+use std::time::Duration;
+use tauri::{Emitter, Manager};
 use tokio::sync::Mutex;
 
 #[derive(Clone)]
@@ -36,130 +50,327 @@ impl VisorClient {
 
 pub struct VisorClientState {
     pub root_dir: PathBuf,
+    sandbox: Arc<Sandbox>,
+    pub permissions: PermissionBroker,
+    policy: std::sync::Mutex<PermissionPolicy>,
+    commands: CommandPolicy,
+    pub tool_calls: std::sync::Mutex<ToolCallStore>,
+    pub journal: std::sync::Mutex<ChangeJournal>,
+    /// Set when the session stages agent writes for review instead of writing to disk.
+    overlay: Option<std::sync::Mutex<StagedOverlay>>,
+    max_read_bytes: u64,
+    terminal_limits: TerminalLimits,
     app_handle: tauri::AppHandle,
-    terminals: Arc<Mutex<HashMap<TerminalId, Arc<TerminalState>>>>,
+    terminals: Arc<Mutex<HashMap<TerminalId, Arc<AgentTerminal>>>>,
+    turn_terminals: Mutex<Vec<TerminalId>>,
     terminal_counter: AtomicUsize,
+    /// Set while the user records this session's terminals; see `start_recording`.
+    record_terminals: AtomicBool,
 }
 
 impl VisorClientState {
-    pub fn new(root_dir: PathBuf, app_handle: tauri::AppHandle) -> Self {
+    pub fn new(
+        sandbox: Sandbox,
+        policy: PermissionPolicy,
+        commands: CommandPolicy,
+        staged_writes: bool,
+        max_read_bytes: u64,
+        terminal_limits: TerminalLimits,
+        app_handle: tauri::AppHandle,
+    ) -> Self {
+        let root_dir = sandbox.root().to_path_buf();
         Self {
+            sandbox: Arc::new(sandbox),
+            tool_calls: std::sync::Mutex::new(ToolCallStore::new(root_dir.clone())),
+            journal: std::sync::Mutex::new(ChangeJournal::new(root_dir.clone())),
+            overlay: staged_writes
+                .then(|| std::sync::Mutex::new(StagedOverlay::new(root_dir.clone()))),
+            max_read_bytes,
+            terminal_limits,
             root_dir,
+            permissions: PermissionBroker::new(),
+            policy: std::sync::Mutex::new(policy),
+            commands,
             app_handle,
             terminals: Arc::new(Mutex::new(HashMap::new())),
+            turn_terminals: Mutex::new(Vec::new()),
             terminal_counter: AtomicUsize::new(1),
+            record_terminals: AtomicBool::new(false),
         }
     }
 
-    fn next_terminal_id(&self) -> TerminalId {
-        let next = self.terminal_counter.fetch_add(1, Ordering::SeqCst);
-        TerminalId::new(format!("term-{next}"))
+    pub async fn begin_turn(&self, prompt_id: &str) {
+        self.turn_terminals.lock().await.clear();
+        if let Ok(mut journal) = self.journal.lock() {
+            journal.checkpoint(prompt_id);
+        }
     }
 
-    fn emit_event(&self, event: AcpUiEvent) {
-        let _ = self.app_handle.emit("acp://update", event);
+    pub fn end_turn(&self) {
+        if let Ok(mut journal) = self.journal.lock() {
+            journal.end_turn();
+        }
+    }
+
+    /// Settles everything the current turn left outstanding after a `session/cancel`:
+    /// pending permission prompts resolve as cancelled and terminals it created are killed.
+    pub async fn cancel_turn(&self) {
+        self.permissions.cancel_all();
+
+        let turn_terminals = std::mem::take(&mut *self.turn_terminals.lock().await);
+        let terminals = self.terminals.lock().await;
+        for terminal_id in turn_terminals {
+            if let Some(terminal) = terminals.get(&terminal_id) {
+                terminal.kill();
+            }
+        }
     }
 
-    fn validate_path(&self, path: &Path, allow_missing: bool) -> Result<PathBuf> {
-        if path.components().any(|c| matches!(c, Component::ParentDir)) {
-            return Err(Error::invalid_params().data("parent paths are not allowed"));
+    /// Kills every terminal the agent created, with everything they started, and forgets
+    /// them. Run when the session shuts down.
+    pub async fn shutdown_terminals(&self) {
+        let terminals = std::mem::take(&mut *self.terminals.lock().await);
+        for terminal in terminals.values() {
+            terminal.kill();
         }
+    }
 
-        let root = &self.root_dir;
-        let candidate = if path.is_absolute() {
-            path.to_path_buf()
-        } else {
-            root.join(path)
+    /// Records every terminal the agent creates from now on, from its first byte, under
+    /// the project's `.acp/recordings`. Terminals already running are not recorded.
+    pub fn start_recording(&self) {
+        self.record_terminals.store(true, Ordering::SeqCst);
+    }
+
+    /// Stops recording new terminals and ends the recordings of those still registered,
+    /// returning their files.
+    pub async fn stop_recording(&self) -> Vec<PathBuf> {
+        self.record_terminals.store(false, Ordering::SeqCst);
+        let terminals = self.terminals.lock().await;
+        terminals
+            .values()
+            .filter_map(|terminal| terminal.stop_recording())
+            .collect()
+    }
+
+    pub fn staged_writes(&self) -> bool {
+        self.overlay.is_some()
+    }
+
+    fn overlay(&self) -> std::result::Result<std::sync::MutexGuard<'_, StagedOverlay>, String> {
+        self.overlay
+            .as_ref()
+            .ok_or_else(|| "session is not staging writes".to_string())?
+            .lock()
+            .map_err(|_| "staged overlay poisoned".to_string())
+    }
+
+    /// Reverts the journaled changes in `scope` inside this session's sandbox. Blocks on
+    /// file IO with the journal locked, so async callers run it on a blocking worker.
+    pub fn rollback(&self, scope: RollbackScope) -> std::result::Result<Vec<String>, String> {
+        self.journal
+            .lock()
+            .map_err(|_| "change journal poisoned".to_string())?
+            .rollback(&self.sandbox, scope)
+    }
+
+    pub fn staged_files(&self) -> std::result::Result<Vec<UiStagedFile>, String> {
+        Ok(self.overlay()?.files())
+    }
+
+    /// Writes staged content for `path` to disk, all of it or a single hunk, recording it in
+    /// the journal like any other agent write. Returns the journal entry and what remains
+    /// staged for the file.
+    pub async fn accept_staged(
+        &self,
+        path: &Path,
+        hunk: Option<usize>,
+    ) -> std::result::Result<(UiFileChange, Option<UiStagedFile>), String> {
+        let path = resolve_in_root(path, &self.root_dir);
+        let (content, write) = {
+            let overlay = self.overlay()?;
+            let write = overlay
+                .get(&path)
+                .cloned()
+                .ok_or_else(|| format!("no staged changes for {}", path.display()))?;
+            let content = match hunk {
+                Some(index) => overlay.accept_hunk(&path, index)?,
+                None => write.content.clone(),
+            };
+            (content, write)
         };
 
-        let canonical = match candidate.canonicalize() {
-            Ok(path) => path,
-            Err(err) if allow_missing => {
-                let parent = candidate
-                    .parent()
-                    .ok_or_else(|| Error::invalid_params().data("path has no parent"))?;
-                let canonical_parent = parent
-                    .canonicalize()
-                    .map_err(|_| Error::invalid_params().data("invalid parent path"))?;
-                let file_name = candidate
-                    .file_name()
-                    .ok_or_else(|| Error::invalid_params().data("path has no file name"))?;
-                canonical_parent.join(file_name)
+        let change = self
+            .write_through(
+                path.clone(),
+                content.clone(),
+                write.tool_call_id,
+                write.prompt_id,
+            )
+            .await?;
+
+        let mut overlay = self.overlay()?;
+        if let Some(staged) = overlay.read(&path).map(str::to_string) {
+            overlay.settle(&path, staged, Some(&content));
+        }
+        Ok((change, overlay.file(&path)))
+    }
+
+    /// Discards staged content for `path`, all of it or a single hunk. Returns what remains.
+    pub fn reject_staged(
+        &self,
+        path: &Path,
+        hunk: Option<usize>,
+    ) -> std::result::Result<Option<UiStagedFile>, String> {
+        let path = resolve_in_root(path, &self.root_dir);
+        let mut overlay = self.overlay()?;
+        match hunk {
+            Some(index) => overlay.reject_hunk(&path, index)?,
+            None => {
+                overlay
+                    .remove(&path)
+                    .ok_or_else(|| format!("no staged changes for {}", path.display()))?;
             }
-            Err(err) => {
-                return Err(Error::invalid_params().data(format!(
-                    "failed to resolve path: {err}"
-                )))
+        }
+        Ok(overlay.file(&path))
+    }
+
+    /// Writes to the real tree and records the change in the journal.
+    async fn write_through(
+        &self,
+        path: PathBuf,
+        content: String,
+        tool_call_id: Option<String>,
+        prompt_id: Option<String>,
+    ) -> std::result::Result<UiFileChange, String> {
+        let previous = read_previous(&path).await?;
+        let sandbox = self.sandbox.clone();
+        let previous_for_write = previous.clone();
+        let (path, content) = tauri::async_runtime::spawn_blocking(move || {
+            if let Some(parent) = path.parent() {
+                sandbox.create_dirs(parent)?;
             }
-        };
+            write_text(&sandbox, &path, &content, previous_for_write.as_deref())
+        })
+        .await
+        .map_err(|err| format!("write task failed: {err}"))??;
+
+        let mut journal = self
+            .journal
+            .lock()
+            .map_err(|_| "change journal poisoned".to_string())?;
+        Ok(journal.record(path, previous, content, tool_call_id, prompt_id))
+    }
 
-        if !canonical.starts_with(root) {
-            return Err(Error::invalid_params().data("path is outside project root"));
+    /// Unsaved editor content for `path`, if the user has pending edits to it.
+    fn dirty_buffer(&self, path: &Path) -> Option<String> {
+        self.app_handle.try_state::<BufferRegistry>()?.read(path)
+    }
+
+    /// Routes an agent write into the user's dirty buffer, if there is one, so pending
+    /// edits are not clobbered on disk. Returns `false` when the file is not dirty.
+    fn write_dirty_buffer(&self, path: &Path, content: &str) -> bool {
+        let Some(buffers) = self.app_handle.try_state::<BufferRegistry>() else {
+            return false;
+        };
+        if !buffers.write_if_dirty(path, content) {
+            return false;
         }
+        notify_buffer_updated(&self.app_handle, path, content);
+        true
+    }
 
-        Ok(canonical)
+    async fn terminal(&self, terminal_id: &TerminalId) -> Option<Arc<AgentTerminal>> {
+        self.terminals.lock().await.get(terminal_id).cloned()
     }
-}
 
-#[derive(Debug, Clone, Serialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
-#[allow(dead_code)]
-pub enum AcpUiEvent {
-    ChatMessage { session_id: String, content: String },
-    StatusUpdate { session_id: String, content: String },
-    Error { session_id: String, content: String },
-}
+    /// Looks up one of this agent's terminals for a command the UI sent, by the id the UI
+    /// was given. Not to be confused with the user's own PTYs in `terminal::AppState`.
+    async fn agent_terminal(
+        &self,
+        terminal_id: &str,
+    ) -> std::result::Result<Arc<AgentTerminal>, String> {
+        self.terminal(&TerminalId::new(terminal_id))
+            .await
+            .ok_or_else(|| format!("unknown terminal: {terminal_id}"))
+    }
 
-struct TerminalState {
-    output: Mutex<TerminalOutputState>,
-    child: Mutex<tokio::process::Child>,
-    exit_status: Mutex<Option<TerminalExitStatus>>,
-    output_limit: Option<u64>,
-}
+    /// Types into an agent terminal on the user's behalf. The PTY echoes the input, so the
+    /// agent sees it in `terminal/output` like everything else the command printed.
+    pub async fn write_terminal(
+        &self,
+        terminal_id: &str,
+        data: &str,
+    ) -> std::result::Result<(), String> {
+        self.agent_terminal(terminal_id)
+            .await?
+            .write_input(data.as_bytes())
+    }
 
-struct TerminalOutputState {
-    output: String,
-    truncated: bool,
-}
+    pub async fn resize_terminal(
+        &self,
+        terminal_id: &str,
+        cols: u16,
+        rows: u16,
+    ) -> std::result::Result<(), String> {
+        self.agent_terminal(terminal_id).await?.resize(PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        })
+    }
 
-impl TerminalOutputState {
-    fn new() -> Self {
-        Self {
-            output: String::new(),
-            truncated: false,
-        }
+    /// Kills an agent terminal's command from the UI. The terminal stays registered so the
+    /// agent's `wait_for_exit` and `terminal/output` report the exit as usual.
+    pub async fn kill_terminal(&self, terminal_id: &str) -> std::result::Result<(), String> {
+        self.agent_terminal(terminal_id).await?.kill();
+        Ok(())
     }
 
-    fn append(&mut self, chunk: &str, limit: Option<u64>) {
-        self.output.push_str(chunk);
-        if let Some(limit) = limit {
-            let limit = limit as usize;
-            if self.output.len() > limit {
-                self.truncated = true;
-                let mut start = self.output.len().saturating_sub(limit);
-                while !self.output.is_char_boundary(start) {
-                    start += 1;
-                }
-                self.output = self.output[start..].to_string();
-            }
-        }
+    fn next_terminal_id(&self) -> TerminalId {
+        let next = self.terminal_counter.fetch_add(1, Ordering::SeqCst);
+        TerminalId::new(format!("term-{next}"))
+    }
+
+    pub fn emit_event(&self, event: AcpUiEvent) {
+        let _ = self.app_handle.emit("acp://update", event);
+    }
+
+    /// Resolves an agent-supplied path through the sandbox.
+    fn resolve_path(&self, path: &Path) -> Result<PathBuf> {
+        self.sandbox
+            .resolve(path)
+            .map_err(|err| Error::invalid_params().data(err))
     }
 }
 
+/// Size of the PTY agent commands run in; wide enough that tools don't wrap their output.
+const AGENT_TERMINAL_SIZE: PtySize = PtySize {
+    rows: 40,
+    cols: 120,
+    pixel_width: 0,
+    pixel_height: 0,
+};
+
 impl MessageHandler<agent_client_protocol::ClientSide> for VisorClient {
-    fn handle_request(&self, request: AgentRequest) -> impl std::future::Future<Output = Result<ClientResponse>> {
+    fn handle_request(
+        &self,
+        request: AgentRequest,
+    ) -> impl std::future::Future<Output = Result<ClientResponse>> {
         let state = self.state.clone();
         async move {
             match request {
-                AgentRequest::RequestPermissionRequest(req) => {
-                    Ok(ClientResponse::RequestPermissionResponse(handle_permission(req)))
-                }
-                AgentRequest::ReadTextFileRequest(req) => {
-                    Ok(ClientResponse::ReadTextFileResponse(handle_read_text(&state, req).await?))
-                }
+                AgentRequest::RequestPermissionRequest(req) => Ok(
+                    ClientResponse::RequestPermissionResponse(handle_permission(&state, req).await),
+                ),
+                AgentRequest::ReadTextFileRequest(req) => Ok(ClientResponse::ReadTextFileResponse(
+                    handle_read_text(&state, req).await?,
+                )),
                 AgentRequest::WriteTextFileRequest(req) => {
                     handle_write_text(&state, req).await?;
-                    Ok(ClientResponse::WriteTextFileResponse(WriteTextFileResponse::new()))
+                    Ok(ClientResponse::WriteTextFileResponse(
+                        WriteTextFileResponse::new(),
+                    ))
                 }
                 AgentRequest::CreateTerminalRequest(req) => {
                     let response = handle_create_terminal(&state, req).await?;
@@ -171,7 +382,9 @@ impl MessageHandler<agent_client_protocol::ClientSide> for VisorClient {
                 }
                 AgentRequest::ReleaseTerminalRequest(req) => {
                     handle_release_terminal(&state, req).await?;
-                    Ok(ClientResponse::ReleaseTerminalResponse(ReleaseTerminalResponse::new()))
+                    Ok(ClientResponse::ReleaseTerminalResponse(
+                        ReleaseTerminalResponse::new(),
+                    ))
                 }
                 AgentRequest::WaitForTerminalExitRequest(req) => {
                     let response = handle_wait_for_exit(&state, req).await?;
@@ -179,11 +392,12 @@ impl MessageHandler<agent_client_protocol::ClientSide> for VisorClient {
                 }
                 AgentRequest::KillTerminalCommandRequest(req) => {
                     handle_kill_terminal(&state, req).await?;
-                    Ok(ClientResponse::KillTerminalResponse(KillTerminalCommandResponse::new()))
-                }
-                AgentRequest::ExtMethodRequest(ext) => {
-                    Err(Error::method_not_found().data(format!("unsupported ext method {}", ext.method)))
+                    Ok(ClientResponse::KillTerminalResponse(
+                        KillTerminalCommandResponse::new(),
+                    ))
                 }
+                AgentRequest::ExtMethodRequest(ext) => Err(Error::method_not_found()
+                    .data(format!("unsupported ext method {}", ext.method))),
                 _ => Err(Error::method_not_found().data("unsupported request type")),
             }
         }
@@ -207,184 +421,305 @@ impl MessageHandler<agent_client_protocol::ClientSide> for VisorClient {
     }
 }
 
-fn handle_permission(request: RequestPermissionRequest) -> RequestPermissionResponse {
-    let selected = request
-        .options
-        .iter()
-        .find(|option| matches!(option.kind, PermissionOptionKind::AllowOnce))
-        .or_else(|| request.options.iter().find(|option| matches!(option.kind, PermissionOptionKind::AllowAlways)))
-        .or_else(|| request.options.first())
-        .map(|option| SelectedPermissionOutcome::new(option.option_id.clone()));
-
-    let outcome = match selected {
-        Some(selection) => RequestPermissionOutcome::Selected(selection),
-        None => RequestPermissionOutcome::Cancelled,
-    };
+async fn handle_permission(
+    state: &VisorClientState,
+    request: RequestPermissionRequest,
+) -> RequestPermissionResponse {
+    let session_id = request.session_id.to_string();
+    if let Ok(mut store) = state.tool_calls.lock() {
+        store.apply(request.tool_call.clone());
+    }
+    let title = request
+        .tool_call
+        .fields
+        .title
+        .clone()
+        .unwrap_or_else(|| request.tool_call.tool_call_id.to_string());
+
+    let decision = state
+        .policy
+        .lock()
+        .map(|policy| policy.evaluate(&request.tool_call))
+        .unwrap_or(PolicyDecision::Ask);
+    if let Some(outcome) = decision.select(&request.options) {
+        let verdict = if decision == PolicyDecision::Allow {
+            "Allowed"
+        } else {
+            "Denied"
+        };
+        state.emit_event(AcpUiEvent::StatusUpdate {
+            session_id,
+            content: format!("{verdict} by policy: {title}"),
+        });
+        return RequestPermissionResponse::new(outcome);
+    }
+
+    let (request_id, response_rx) = state.permissions.register(&request.options);
+    state.emit_event(AcpUiEvent::PermissionRequest {
+        session_id: session_id.clone(),
+        request_id: request_id.clone(),
+        tool_call: UiToolCallUpdate::from(&request.tool_call),
+        options: request
+            .options
+            .iter()
+            .map(UiPermissionOption::from)
+            .collect(),
+    });
+
+    let outcome = state
+        .permissions
+        .wait(&request_id, response_rx, PERMISSION_TIMEOUT)
+        .await;
+
+    if let RequestPermissionOutcome::Selected(selected) = &outcome {
+        let remembered = request
+            .options
+            .iter()
+            .find(|option| option.option_id == selected.option_id)
+            .and_then(|option| PolicyDecision::from_remembered_kind(option.kind));
+        if let Some(decision) = remembered {
+            let result = state
+                .policy
+                .lock()
+                .map_err(|_| "policy mutex poisoned".to_string())
+                .and_then(|mut policy| policy.remember(&request.tool_call, decision));
+            if let Err(err) = result {
+                state.emit_event(AcpUiEvent::Error {
+                    session_id: session_id.clone(),
+                    content: format!("failed to remember permission choice: {err}"),
+                });
+            }
+        }
+    }
 
+    state.emit_event(AcpUiEvent::PermissionResolved {
+        session_id,
+        request_id,
+        option_id: selected_option_id(&outcome),
+    });
     RequestPermissionResponse::new(outcome)
 }
 
-async fn handle_read_text(state: &VisorClientState, req: ReadTextFileRequest) -> Result<ReadTextFileResponse> {
-    let path = state.validate_path(&req.path, false)?;
-    let content = tokio::fs::read_to_string(&path)
-        .await
-        .map_err(|err| Error::internal_error().data(format!("failed to read file: {err}")))?;
-
-    let content = if req.line.is_some() || req.limit.is_some() {
-        let start = req.line.unwrap_or(1).saturating_sub(1) as usize;
-        let limit = req.limit.unwrap_or(u32::MAX) as usize;
-        content
-            .lines()
-            .skip(start)
-            .take(limit)
-            .collect::<Vec<_>>()
-            .join("\n")
-    } else {
-        content
+async fn handle_read_text(
+    state: &VisorClientState,
+    req: ReadTextFileRequest,
+) -> Result<ReadTextFileResponse> {
+    let path = state.resolve_path(&req.path)?;
+    let staged = match state.overlay() {
+        Ok(overlay) => overlay.read(&path).map(str::to_string),
+        Err(_) => None,
     };
-
+    let content = match staged.or_else(|| state.dirty_buffer(&path)) {
+        Some(content) => window_text(&content, req.line, req.limit, state.max_read_bytes),
+        None => read_text(&path, req.line, req.limit, state.max_read_bytes).await,
+    }
+    .map_err(|err| err.into_acp(&req.path))?;
     Ok(ReadTextFileResponse::new(content))
 }
 
 async fn handle_write_text(state: &VisorClientState, req: WriteTextFileRequest) -> Result<()> {
-    let path = state.validate_path(&req.path, true)?;
-    if let Some(parent) = path.parent() {
-        tokio::fs::create_dir_all(parent)
+    let path = state.resolve_path(&req.path)?;
+    let session_id = req.session_id.to_string();
+    let tool_call_id = state
+        .tool_calls
+        .lock()
+        .ok()
+        .and_then(|store| store.attribute_write(&path));
+    let prompt_id = state
+        .journal
+        .lock()
+        .ok()
+        .and_then(|journal| journal.current_turn());
+
+    if state.staged_writes() {
+        let display = display_relative(&path, &state.root_dir);
+        let previous = read_previous(&path)
             .await
-            .map_err(|err| Error::internal_error().data(format!("failed to create dirs: {err}")))?;
+            .map_err(|err| Error::internal_error().data(err))?;
+        let write = StagedWrite {
+            content: conform_to_previous(&req.content, previous.as_deref()),
+            tool_call_id,
+            prompt_id,
+        };
+        let file = state
+            .overlay()
+            .map_err(|err| Error::internal_error().data(err))?
+            .stage(path, write);
+        state.emit_event(AcpUiEvent::Staged {
+            session_id,
+            path: display,
+            file: Some(file),
+        });
+        return Ok(());
     }
-    tokio::fs::write(&path, req.content)
+
+    if state.write_dirty_buffer(&path, &req.content) {
+        state.emit_event(AcpUiEvent::StatusUpdate {
+            session_id,
+            content: format!(
+                "Updated unsaved buffer {}",
+                display_relative(&path, &state.root_dir)
+            ),
+        });
+        return Ok(());
+    }
+
+    let change = state
+        .write_through(path, req.content, tool_call_id, prompt_id)
         .await
-        .map_err(|err| Error::internal_error().data(format!("failed to write file: {err}")))?;
+        .map_err(|err| Error::internal_error().data(err))?;
+    state.emit_event(AcpUiEvent::FileChanged { session_id, change });
     Ok(())
 }
 
+/// The bytes a write is about to replace, or `None` when it creates the file.
+async fn read_previous(path: &Path) -> std::result::Result<Option<Vec<u8>>, String> {
+    match tokio::fs::read(path).await {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(format!("failed to read file: {err}")),
+    }
+}
+
 async fn handle_create_terminal(
     state: &VisorClientState,
     req: CreateTerminalRequest,
 ) -> Result<CreateTerminalResponse> {
     let terminal_id = state.next_terminal_id();
     let cwd = match &req.cwd {
-        Some(path) => state.validate_path(path, false)?,
+        Some(path) => {
+            let cwd = state.resolve_path(path)?;
+            if !cwd.is_dir() {
+                return Err(Error::invalid_params().data("cwd is not a directory"));
+            }
+            cwd
+        }
         None => state.root_dir.clone(),
     };
 
-    let mut command = Command::new(&req.command);
-    command.args(&req.args).current_dir(cwd).stdin(std::process::Stdio::piped());
-    command.stdout(std::process::Stdio::piped());
-    command.stderr(std::process::Stdio::piped());
+    let session_id = req.session_id.to_string();
+    if let Err(denied) = state.commands.check(&req.command, &req.args) {
+        state.emit_event(AcpUiEvent::CommandDenied {
+            session_id,
+            command: denied.command.clone(),
+            message: denied.message(),
+        });
+        return Err(denied.into_acp());
+    }
 
+    let command_line = std::iter::once(&req.command)
+        .chain(&req.args)
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut command = limited_command(&req.command, &req.args, &state.terminal_limits);
+    state.commands.filter_env(&mut command);
+    command.cwd(cwd);
     for env_var in &req.env {
         command.env(&env_var.name, &env_var.value);
     }
 
-    let mut child = command
-        .spawn()
-        .map_err(|err| Error::internal_error().data(format!("failed to spawn terminal: {err}")))?;
-
-    let stdout = child.stdout.take();
-    let stderr = child.stderr.take();
+    // A recording that cannot be created must not stop the agent's command, but the user
+    // is told it runs unrecorded.
+    let recorder = if state.record_terminals.load(Ordering::SeqCst) {
+        Recorder::create(
+            &state.root_dir,
+            &format!("agent-{terminal_id}"),
+            &command_line,
+            AGENT_TERMINAL_SIZE.cols,
+            AGENT_TERMINAL_SIZE.rows,
+        )
+        .map_err(|err| {
+            state.emit_event(AcpUiEvent::Error {
+                session_id: session_id.clone(),
+                content: format!("Terminal {terminal_id} is not being recorded: {err}"),
+            });
+        })
+        .ok()
+    } else {
+        None
+    };
 
-    let terminal_state = Arc::new(TerminalState {
-        output: Mutex::new(TerminalOutputState::new()),
-        child: Mutex::new(child),
-        exit_status: Mutex::new(None),
-        output_limit: req.output_byte_limit,
-    });
+    let app_handle = state.app_handle.clone();
+    let (output_session, output_id) = (session_id.clone(), terminal_id.to_string());
+    let (exit_session, exit_id) = (session_id.clone(), terminal_id.to_string());
+    let terminal = AgentTerminal::spawn(
+        command,
+        AGENT_TERMINAL_SIZE,
+        req.output_byte_limit,
+        state.terminal_limits.timeout_secs.map(Duration::from_secs),
+        recorder,
+        {
+            let app_handle = app_handle.clone();
+            move |data| {
+                let _ = app_handle.emit(
+                    "acp://update",
+                    AcpUiEvent::TerminalOutput {
+                        session_id: output_session.clone(),
+                        terminal_id: output_id.clone(),
+                        data: data.to_string(),
+                    },
+                );
+            }
+        },
+        move |exit| {
+            let _ = app_handle.emit(
+                "acp://update",
+                AcpUiEvent::TerminalExited {
+                    session_id: exit_session,
+                    terminal_id: exit_id,
+                    exit_code: exit.status.exit_code,
+                    signal: exit.status.signal.clone(),
+                    timed_out: exit.timed_out,
+                },
+            );
+        },
+    )
+    .map_err(|err| Error::internal_error().data(format!("failed to spawn terminal: {err}")))?;
 
     let mut terminals = state.terminals.lock().await;
-    terminals.insert(terminal_id.clone(), terminal_state.clone());
+    terminals.insert(terminal_id.clone(), terminal);
     drop(terminals);
+    state.turn_terminals.lock().await.push(terminal_id.clone());
 
-    if let Some(stdout) = stdout {
-        let terminal_state = terminal_state.clone();
-        tauri::async_runtime::spawn(async move {
-            read_terminal_output(stdout, terminal_state).await;
-        });
-    }
-
-    if let Some(stderr) = stderr {
-        let terminal_state = terminal_state.clone();
-        tauri::async_runtime::spawn(async move {
-            read_terminal_output(stderr, terminal_state).await;
-        });
-    }
+    state.emit_event(AcpUiEvent::TerminalStarted {
+        session_id,
+        terminal_id: terminal_id.to_string(),
+        command: command_line,
+    });
 
     Ok(CreateTerminalResponse::new(terminal_id))
 }
 
-async fn read_terminal_output<R>(mut reader: R, terminal_state: Arc<TerminalState>)
-where
-    R: tokio::io::AsyncRead + Unpin + Send + 'static,
-{
-    let mut buffer = [0u8; 1024];
-    loop {
-        match reader.read(&mut buffer).await {
-            Ok(0) => break,
-            Ok(n) => {
-                let chunk = String::from_utf8_lossy(&buffer[..n]);
-                let mut output = terminal_state.output.lock().await;
-                output.append(&chunk, terminal_state.output_limit);
-            }
-            Err(_) => break,
-        }
-    }
-}
-
 async fn handle_terminal_output(
     state: &VisorClientState,
     req: TerminalOutputRequest,
 ) -> Result<TerminalOutputResponse> {
-    let terminal_state = {
-        let terminals = state.terminals.lock().await;
-        terminals
-            .get(&req.terminal_id)
-            .cloned()
-            .ok_or_else(|| Error::invalid_params().data("terminal not found"))?
-    };
-
-    if terminal_state.exit_status.lock().await.is_none() {
-        let mut child = terminal_state.child.lock().await;
-        if let Ok(Some(status)) = child.try_wait() {
-            let exit_status = exit_status_from_process(status);
-            *terminal_state.exit_status.lock().await = Some(exit_status);
-        }
-    }
-
-    let output_state = terminal_state.output.lock().await;
-    let exit_status = terminal_state.exit_status.lock().await.clone();
+    let terminal = state
+        .terminal(&req.terminal_id)
+        .await
+        .ok_or_else(|| Error::invalid_params().data("terminal not found"))?;
 
-    Ok(TerminalOutputResponse::new(
-        output_state.output.clone(),
-        output_state.truncated,
-    )
-    .exit_status(exit_status))
+    // Read the status first: once it is set the watcher has drained the PTY, so the output
+    // that follows is complete.
+    let exit_status = terminal.exit_status();
+    let (output, truncated) = terminal.output();
+    Ok(TerminalOutputResponse::new(output, truncated).exit_status(exit_status))
 }
 
 async fn handle_wait_for_exit(
     state: &VisorClientState,
     req: WaitForTerminalExitRequest,
 ) -> Result<WaitForTerminalExitResponse> {
-    let terminal_state = {
-        let terminals = state.terminals.lock().await;
-        terminals
-            .get(&req.terminal_id)
-            .cloned()
-            .ok_or_else(|| Error::invalid_params().data("terminal not found"))?
-    };
-
-    if let Some(exit_status) = terminal_state.exit_status.lock().await.clone() {
-        return Ok(WaitForTerminalExitResponse::new(exit_status));
-    }
+    let terminal = state
+        .terminal(&req.terminal_id)
+        .await
+        .ok_or_else(|| Error::invalid_params().data("terminal not found"))?;
 
-    let mut child = terminal_state.child.lock().await;
-    let status = child
+    let exit_status = terminal
         .wait()
         .await
-        .map_err(|err| Error::internal_error().data(format!("wait failed: {err}")))?;
-    let exit_status = exit_status_from_process(status);
-    *terminal_state.exit_status.lock().await = Some(exit_status.clone());
-
+        .map_err(|err| Error::internal_error().data(err))?;
     Ok(WaitForTerminalExitResponse::new(exit_status))
 }
 
@@ -392,16 +727,12 @@ async fn handle_kill_terminal(
     state: &VisorClientState,
     req: KillTerminalCommandRequest,
 ) -> Result<()> {
-    let terminal_state = {
-        let terminals = state.terminals.lock().await;
-        terminals
-            .get(&req.terminal_id)
-            .cloned()
-            .ok_or_else(|| Error::invalid_params().data("terminal not found"))?
-    };
+    let terminal = state
+        .terminal(&req.terminal_id)
+        .await
+        .ok_or_else(|| Error::invalid_params().data("terminal not found"))?;
 
-    let mut child = terminal_state.child.lock().await;
-    let _ = child.kill().await;
+    terminal.kill();
     Ok(())
 }
 
@@ -409,66 +740,75 @@ async fn handle_release_terminal(
     state: &VisorClientState,
     req: ReleaseTerminalRequest,
 ) -> Result<()> {
-    let terminal_state = {
+    let terminal = {
         let mut terminals = state.terminals.lock().await;
         terminals.remove(&req.terminal_id)
     };
-    if let Some(terminal_state) = terminal_state {
-        let mut child = terminal_state.child.lock().await;
-        let _ = child.kill().await;
+    if let Some(terminal) = terminal {
+        terminal.kill();
     }
     Ok(())
 }
 
 fn emit_session_update(state: &VisorClientState, note: SessionNotification) {
     let session_id = note.session_id.to_string();
-    match note.update {
-        SessionUpdate::AgentMessageChunk(chunk) => {
-            if let Some(text) = content_block_to_text(&chunk.content) {
-                state.emit_event(AcpUiEvent::ChatMessage { session_id, content: text });
-            }
-        }
+    let event = match note.update {
+        SessionUpdate::AgentMessageChunk(chunk) => AcpUiEvent::AgentMessage {
+            session_id,
+            content: UiContent::from(&chunk.content),
+        },
+        SessionUpdate::AgentThoughtChunk(chunk) => AcpUiEvent::AgentThought {
+            session_id,
+            content: UiContent::from(&chunk.content),
+        },
+        SessionUpdate::UserMessageChunk(chunk) => AcpUiEvent::UserMessage {
+            session_id,
+            content: UiContent::from(&chunk.content),
+        },
         SessionUpdate::ToolCall(tool_call) => {
-            let status = format!("{} ({:?})", tool_call.title, tool_call.status);
-            state.emit_event(AcpUiEvent::StatusUpdate { session_id, content: status });
+            let Ok(mut store) = state.tool_calls.lock() else {
+                return;
+            };
+            AcpUiEvent::ToolCall {
+                session_id,
+                tool_call: store.record(tool_call),
+            }
         }
         SessionUpdate::ToolCallUpdate(update) => {
-            let status = format!("Tool update: {:?}", update.fields.status);
-            state.emit_event(AcpUiEvent::StatusUpdate { session_id, content: status });
-        }
-        SessionUpdate::Plan(plan) => {
-            state.emit_event(AcpUiEvent::StatusUpdate {
+            let Ok(mut store) = state.tool_calls.lock() else {
+                return;
+            };
+            AcpUiEvent::ToolCallUpdate {
                 session_id,
-                content: format!("Plan received: {} steps", plan.entries.len()),
-            });
-        }
-        _ => {}
-    }
-}
-
-fn content_block_to_text(content: &ContentBlock) -> Option<String> {
-    match content {
-        ContentBlock::Text(text) => Some(text.text.clone()),
-        ContentBlock::ResourceLink(link) => Some(link.uri.clone()),
-        _ => None,
-    }
-}
-
-fn exit_status_from_process(status: std::process::ExitStatus) -> TerminalExitStatus {
-    let mut exit_status = TerminalExitStatus::new();
-    exit_status = exit_status.exit_code(status.code().map(|code| code as u32));
-
-    #[cfg(unix)]
-    {
-        use std::os::unix::process::ExitStatusExt;
-        if let Some(signal) = status.signal() {
-            exit_status = exit_status.signal(Some(signal.to_string()));
+                update: UiToolCallUpdate::from(&update),
+                tool_call: store.apply(update),
+            }
         }
-    }
-
-    exit_status
+        SessionUpdate::Plan(plan) => AcpUiEvent::Plan {
+            session_id,
+            entries: plan.entries.iter().map(UiPlanEntry::from).collect(),
+        },
+        SessionUpdate::AvailableCommandsUpdate(update) => AcpUiEvent::AvailableCommands {
+            session_id,
+            commands: update
+                .available_commands
+                .iter()
+                .map(UiCommand::from)
+                .collect(),
+        },
+        SessionUpdate::CurrentModeUpdate(update) => AcpUiEvent::CurrentMode {
+            session_id,
+            mode_id: update.current_mode_id.to_string(),
+        },
+        _ => return,
+    };
+    state.emit_event(event);
 }
 
 pub fn default_client_capabilities() -> ClientCapabilities {
-    ClientCapabilities::new().fs(FileSystemCapability::new().read_text_file(true).write_text_file(true)).terminal(true)
+    ClientCapabilities::new()
+        .fs(FileSystemCapability::new()
+            .read_text_file(true)
+            .write_text_file(true))
+        .terminal(true)
 }
@@ -0,0 +1,459 @@
+use crate::acp::events::{UiCheckpoint, UiFileChange};
+use crate::acp::sandbox::Sandbox;
+use crate::acp::text_file::atomic_write;
+use crate::acp::tool_calls::{display_relative, resolve_in_root};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One agent write: what the file held before (or `None` if it did not exist) and what was
+/// written. Contents are kept as raw bytes so the previous state can be restored exactly.
+pub struct JournalEntry {
+    pub change_id: u64,
+    pub path: PathBuf,
+    pub previous: Option<Vec<u8>>,
+    pub content: Vec<u8>,
+    pub tool_call_id: Option<String>,
+    pub prompt_id: Option<String>,
+    pub timestamp_ms: u64,
+}
+
+impl JournalEntry {
+    fn to_ui(&self, root_dir: &Path) -> UiFileChange {
+        UiFileChange {
+            change_id: self.change_id,
+            path: display_relative(&self.path, root_dir),
+            previous_content: self
+                .previous
+                .as_ref()
+                .map(|bytes| String::from_utf8_lossy(bytes).into_owned()),
+            new_content: String::from_utf8_lossy(&self.content).into_owned(),
+            tool_call_id: self.tool_call_id.clone(),
+            prompt_id: self.prompt_id.clone(),
+            timestamp_ms: self.timestamp_ms,
+        }
+    }
+}
+
+/// Marks where a prompt turn started in the journal; every change from `first_change_id`
+/// on happened during or after that turn.
+struct Checkpoint {
+    prompt_id: String,
+    first_change_id: u64,
+    timestamp_ms: u64,
+}
+
+/// Which journaled changes a rollback reverts.
+pub enum RollbackScope {
+    /// Everything since the checkpoint taken when `prompt_id` started, including later turns.
+    Since(String),
+    /// Only the changes made during the `prompt_id` turn.
+    Turn(String),
+    /// One file, back to its state at a checkpoint or, with `None`, at session start.
+    File {
+        path: PathBuf,
+        since: Option<String>,
+    },
+}
+
+/// Append-only record of every file write an agent made during a session.
+pub struct ChangeJournal {
+    root_dir: PathBuf,
+    entries: Vec<JournalEntry>,
+    checkpoints: Vec<Checkpoint>,
+    current_turn: Option<String>,
+    next_id: u64,
+}
+
+impl ChangeJournal {
+    pub fn new(root_dir: PathBuf) -> Self {
+        Self {
+            root_dir,
+            entries: Vec::new(),
+            checkpoints: Vec::new(),
+            current_turn: None,
+            next_id: 1,
+        }
+    }
+
+    /// Takes a checkpoint for a turn that is starting; writes are attributed to it until
+    /// `end_turn`.
+    pub fn checkpoint(&mut self, prompt_id: &str) {
+        self.checkpoints.push(Checkpoint {
+            prompt_id: prompt_id.to_string(),
+            first_change_id: self.next_id,
+            timestamp_ms: now_ms(),
+        });
+        self.current_turn = Some(prompt_id.to_string());
+    }
+
+    pub fn end_turn(&mut self) {
+        self.current_turn = None;
+    }
+
+    pub fn checkpoints(&self) -> Vec<UiCheckpoint> {
+        self.checkpoints
+            .iter()
+            .map(|checkpoint| {
+                let mut changed_paths: Vec<String> = Vec::new();
+                for entry in &self.entries {
+                    if entry.prompt_id.as_deref() != Some(checkpoint.prompt_id.as_str()) {
+                        continue;
+                    }
+                    let path = display_relative(&entry.path, &self.root_dir);
+                    if !changed_paths.contains(&path) {
+                        changed_paths.push(path);
+                    }
+                }
+                UiCheckpoint {
+                    prompt_id: checkpoint.prompt_id.clone(),
+                    timestamp_ms: checkpoint.timestamp_ms,
+                    changed_paths,
+                }
+            })
+            .collect()
+    }
+
+    /// The turn in progress, if any; writes made now belong to it.
+    pub fn current_turn(&self) -> Option<String> {
+        self.current_turn.clone()
+    }
+
+    pub fn record(
+        &mut self,
+        path: PathBuf,
+        previous: Option<Vec<u8>>,
+        content: Vec<u8>,
+        tool_call_id: Option<String>,
+        prompt_id: Option<String>,
+    ) -> UiFileChange {
+        let entry = JournalEntry {
+            change_id: self.next_id,
+            path,
+            previous,
+            content,
+            tool_call_id,
+            prompt_id,
+            timestamp_ms: now_ms(),
+        };
+        self.next_id += 1;
+        let ui = entry.to_ui(&self.root_dir);
+        self.entries.push(entry);
+        ui
+    }
+
+    /// Every change in the order it was made, optionally limited to one file. `path` may be
+    /// absolute or relative to the session root.
+    pub fn changes(&self, path: Option<&Path>) -> Vec<UiFileChange> {
+        let path = path.map(|path| resolve_in_root(path, &self.root_dir));
+        self.entries
+            .iter()
+            .filter(|entry| path.as_ref().is_none_or(|path| &entry.path == path))
+            .map(|entry| entry.to_ui(&self.root_dir))
+            .collect()
+    }
+
+    /// Restores the files touched by the changes in `scope` to their content before the
+    /// earliest of those changes, deleting files the agent created, and drops the reverted
+    /// changes from the journal. Returns the restored paths relative to the root.
+    ///
+    /// Nothing is touched if any file was modified after the last change being reverted,
+    /// either on disk by someone else or by a later turn outside the scope, so a rollback
+    /// never discards edits it does not know about. Nor is anything touched if a file's
+    /// directory no longer resolves inside `sandbox`, and each directory is rechecked again
+    /// right before its file is restored, as for any other agent write.
+    pub fn rollback(
+        &mut self,
+        sandbox: &Sandbox,
+        scope: RollbackScope,
+    ) -> Result<Vec<String>, String> {
+        if let Some(prompt_id) = &self.current_turn {
+            return Err(format!("cannot roll back while {prompt_id} is running"));
+        }
+
+        let selected: HashSet<u64> = match scope {
+            RollbackScope::Since(prompt_id) => {
+                let first = self.checkpoint_start(&prompt_id)?;
+                self.select(|entry| entry.change_id >= first)
+            }
+            RollbackScope::Turn(prompt_id) => {
+                self.checkpoint_start(&prompt_id)?;
+                self.select(|entry| entry.prompt_id.as_deref() == Some(prompt_id.as_str()))
+            }
+            RollbackScope::File { path, since } => {
+                let first = match since {
+                    Some(prompt_id) => self.checkpoint_start(&prompt_id)?,
+                    None => 0,
+                };
+                let path = resolve_in_root(&path, &self.root_dir);
+                self.select(|entry| entry.path == path && entry.change_id >= first)
+            }
+        };
+
+        let mut restores: Vec<(PathBuf, Option<Vec<u8>>)> = Vec::new();
+        let mut conflicts: Vec<String> = Vec::new();
+        for entry in self.entries.iter() {
+            if !selected.contains(&entry.change_id)
+                || restores.iter().any(|(path, _)| path == &entry.path)
+            {
+                continue;
+            }
+            let display = display_relative(&entry.path, &self.root_dir);
+            let Some(latest) = self
+                .entries
+                .iter()
+                .rev()
+                .find(|later| later.path == entry.path)
+            else {
+                continue;
+            };
+            if !selected.contains(&latest.change_id) {
+                conflicts.push(format!("{display} (changed by a later turn)"));
+                continue;
+            }
+            let on_disk = match std::fs::read(&entry.path) {
+                Ok(bytes) => Some(bytes),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+                Err(err) => return Err(format!("failed to read {display}: {err}")),
+            };
+            if on_disk.as_ref() != Some(&latest.content) {
+                conflicts.push(format!("{display} (edited since the agent wrote it)"));
+                continue;
+            }
+            if let Some(parent) = entry.path.parent() {
+                sandbox
+                    .recheck_dir(parent)
+                    .map_err(|err| format!("cannot restore {display}: {err}"))?;
+            }
+            restores.push((entry.path.clone(), entry.previous.clone()));
+        }
+        if !conflicts.is_empty() {
+            return Err(format!(
+                "rollback would discard newer edits: {}",
+                conflicts.join(", ")
+            ));
+        }
+
+        let mut restored = Vec::new();
+        for (path, previous) in restores {
+            let display = display_relative(&path, &self.root_dir);
+            restore(sandbox, &path, previous.as_deref())
+                .map_err(|err| format!("failed to restore {display}: {err}"))?;
+            self.entries
+                .retain(|entry| entry.path != path || !selected.contains(&entry.change_id));
+            restored.push(display);
+        }
+        Ok(restored)
+    }
+
+    fn checkpoint_start(&self, prompt_id: &str) -> Result<u64, String> {
+        self.checkpoints
+            .iter()
+            .find(|checkpoint| checkpoint.prompt_id == prompt_id)
+            .map(|checkpoint| checkpoint.first_change_id)
+            .ok_or_else(|| format!("unknown checkpoint: {prompt_id}"))
+    }
+
+    fn select(&self, predicate: impl Fn(&JournalEntry) -> bool) -> HashSet<u64> {
+        self.entries
+            .iter()
+            .filter(|entry| predicate(entry))
+            .map(|entry| entry.change_id)
+            .collect()
+    }
+}
+
+/// Puts `previous` back at `path`, or deletes the file when there was none, once its
+/// directory is confirmed to still resolve inside the sandbox. The bytes go back exactly as
+/// journaled, so this writes them directly rather than through `write_text`.
+fn restore(sandbox: &Sandbox, path: &Path, previous: Option<&[u8]>) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        sandbox.recheck_dir(parent)?;
+    }
+    let result = match previous {
+        Some(bytes) => atomic_write(path, bytes),
+        None => match std::fs::remove_file(path) {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            other => other,
+        },
+    };
+    result.map_err(|err| err.to_string())
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_are_ordered_and_filterable_by_file() {
+        let root = PathBuf::from("/project");
+        let mut journal = ChangeJournal::new(root.clone());
+
+        let created = journal.record(
+            root.join("src/new.rs"),
+            None,
+            b"fn new() {}".to_vec(),
+            Some("call-1".to_string()),
+            None,
+        );
+        journal.record(
+            root.join("src/main.rs"),
+            Some(b"old".to_vec()),
+            b"new".to_vec(),
+            None,
+            None,
+        );
+        journal.record(
+            root.join("src/new.rs"),
+            Some(b"fn new() {}".to_vec()),
+            b"fn new() -> u8 { 0 }".to_vec(),
+            Some("call-2".to_string()),
+            None,
+        );
+
+        assert_eq!(created.path, "src/new.rs");
+        assert_eq!(created.previous_content, None);
+        assert_eq!(journal.changes(None).len(), 3);
+
+        let history = journal.changes(Some(Path::new("src/new.rs")));
+        let ids: Vec<_> = history.iter().map(|change| change.change_id).collect();
+        assert_eq!(ids, vec![1, 3]);
+        assert_eq!(history[1].previous_content.as_deref(), Some("fn new() {}"));
+        assert_eq!(history[1].tool_call_id.as_deref(), Some("call-2"));
+        assert_eq!(
+            journal.changes(Some(&root.join("src/main.rs")))[0].new_content,
+            "new"
+        );
+    }
+
+    /// Writes through to disk and journals the change, as `handle_write_text` does.
+    fn write(journal: &mut ChangeJournal, path: &Path, content: &str) {
+        let previous = std::fs::read(path).ok();
+        std::fs::write(path, content).unwrap();
+        let prompt_id = journal.current_turn();
+        journal.record(
+            path.to_path_buf(),
+            previous,
+            content.as_bytes().to_vec(),
+            None,
+            prompt_id,
+        );
+    }
+
+    fn turn(prompt_id: &str) -> RollbackScope {
+        RollbackScope::Turn(prompt_id.to_string())
+    }
+
+    #[test]
+    fn rolling_back_a_turn_restores_edits_and_deletes_created_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let sandbox = Sandbox::new(root.clone(), &[], &[]).unwrap();
+        let existing = root.join("existing.txt");
+        let created = root.join("created.txt");
+        std::fs::write(&existing, "original").unwrap();
+        let mut journal = ChangeJournal::new(root.clone());
+
+        journal.checkpoint("prompt-1");
+        write(&mut journal, &existing, "first");
+        write(&mut journal, &existing, "second");
+        write(&mut journal, &created, "new file");
+        assert!(journal.rollback(&sandbox, turn("prompt-1")).is_err());
+        journal.end_turn();
+
+        let mut restored = journal.rollback(&sandbox, turn("prompt-1")).unwrap();
+        restored.sort();
+        assert_eq!(restored, vec!["created.txt", "existing.txt"]);
+        assert_eq!(std::fs::read_to_string(&existing).unwrap(), "original");
+        assert!(!created.exists());
+        assert!(journal.changes(None).is_empty());
+        assert!(journal.checkpoints()[0].changed_paths.is_empty());
+    }
+
+    #[test]
+    fn rollback_refuses_to_discard_newer_edits() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let sandbox = Sandbox::new(root.clone(), &[], &[]).unwrap();
+        let shared = root.join("shared.txt");
+        let other = root.join("other.txt");
+        let mut journal = ChangeJournal::new(root.clone());
+
+        journal.checkpoint("prompt-1");
+        write(&mut journal, &shared, "turn one");
+        write(&mut journal, &other, "turn one");
+        journal.end_turn();
+        journal.checkpoint("prompt-2");
+        write(&mut journal, &shared, "turn two");
+        journal.end_turn();
+
+        let err = journal.rollback(&sandbox, turn("prompt-1")).unwrap_err();
+        assert!(
+            err.contains("shared.txt (changed by a later turn)"),
+            "{err}"
+        );
+        assert!(other.exists());
+
+        std::fs::write(&other, "user edit").unwrap();
+        let err = journal
+            .rollback(
+                &sandbox,
+                RollbackScope::File {
+                    path: PathBuf::from("other.txt"),
+                    since: None,
+                },
+            )
+            .unwrap_err();
+        assert!(
+            err.contains("other.txt (edited since the agent wrote it)"),
+            "{err}"
+        );
+        assert_eq!(std::fs::read_to_string(&other).unwrap(), "user edit");
+
+        assert_eq!(
+            journal
+                .rollback(&sandbox, RollbackScope::Since("prompt-2".to_string()))
+                .unwrap(),
+            vec!["shared.txt"]
+        );
+        assert_eq!(std::fs::read_to_string(&shared).unwrap(), "turn one");
+        assert_eq!(journal.changes(None).len(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rollback_refuses_directories_swapped_for_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let sandbox = Sandbox::new(root.clone(), &[], &[]).unwrap();
+        std::fs::create_dir(root.join("sub")).unwrap();
+        std::fs::write(root.join("sub/edited.txt"), "original").unwrap();
+        let mut journal = ChangeJournal::new(root.clone());
+
+        journal.checkpoint("prompt-1");
+        write(&mut journal, &root.join("sub/edited.txt"), "agent");
+        write(&mut journal, &root.join("sub/created.txt"), "agent");
+        journal.end_turn();
+
+        let moved = outside.path().join("sub");
+        std::fs::rename(root.join("sub"), &moved).unwrap();
+        std::os::unix::fs::symlink(&moved, root.join("sub")).unwrap();
+
+        let err = journal.rollback(&sandbox, turn("prompt-1")).unwrap_err();
+        assert!(err.contains("outside"), "{err}");
+        assert_eq!(
+            std::fs::read_to_string(moved.join("edited.txt")).unwrap(),
+            "agent"
+        );
+        assert!(moved.join("created.txt").exists());
+        assert_eq!(journal.changes(None).len(), 2);
+    }
+}
@@ -1,12 +1,24 @@
+use crate::acp::command_policy::CommandPolicy;
 use crate::acp::config::{AgentConfig, AgentsConfig};
+use crate::acp::events::{
+    AcpUiEvent, UiCheckpoint, UiFileChange, UiStagedFile, UiTrackedToolCall,
+};
 use crate::acp::handler::{default_client_capabilities, VisorClient, VisorClientState};
+use crate::acp::journal::RollbackScope;
+use crate::acp::policy::PermissionPolicy;
+use crate::acp::sandbox::Sandbox;
+use crate::acp::text_file::DEFAULT_MAX_READ_BYTES;
+use crate::acp::tool_calls::{display_relative, resolve_in_root};
 use agent_client_protocol::{
-    Agent, ClientSideConnection, ContentBlock, InitializeRequest, NewSessionRequest, PromptRequest,
-    ProtocolVersion, SessionId,
+    Agent, CancelNotification, ClientSideConnection, ContentBlock, InitializeRequest,
+    NewSessionRequest, PromptRequest, ProtocolVersion, SessionId, SetSessionModeRequest,
+    StopReason,
 };
 use serde::Serialize;
-use std::path::PathBuf;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
+use std::rc::Rc;
 use std::sync::Arc;
 use tauri::async_runtime::JoinHandle;
 use tauri::AppHandle;
@@ -25,18 +37,20 @@ pub struct AgentSummary {
 pub struct AcpSessionInfo {
     pub agent_id: String,
     pub session_id: String,
+    pub root_dir: String,
+    pub staged_writes: bool,
 }
 
 pub struct AcpManager {
     config: AgentsConfig,
-    session: Option<AcpSession>,
+    sessions: HashMap<String, Arc<AcpSession>>,
 }
 
 impl AcpManager {
     pub fn new(config: AgentsConfig) -> Self {
         Self {
             config,
-            session: None,
+            sessions: HashMap::new(),
         }
     }
 
@@ -55,51 +69,40 @@ impl AcpManager {
         self.config.find(id)
     }
 
-    pub fn session_active(&self) -> bool {
-        self.session.is_some()
-    }
-
-    pub async fn start_session(
-        &mut self,
-        app: AppHandle,
-        agent_id: String,
-        root_dir: PathBuf,
-    ) -> Result<AcpSessionInfo, String> {
-        if self.session.is_some() {
-            return Err("ACP session already active".to_string());
+    /// Swaps in a freshly loaded config, refusing if it changes or removes an agent that
+    /// still has a running session.
+    pub fn replace_config(&mut self, config: AgentsConfig) -> Result<(), String> {
+        for session in self.sessions.values() {
+            if self.config.find(&session.agent_id) != config.find(&session.agent_id) {
+                return Err(format!(
+                    "cannot reload config while a session for agent {} is active",
+                    session.agent_id
+                ));
+            }
         }
+        self.config = config;
+        Ok(())
+    }
 
-        let agent = self
-            .find_agent(&agent_id)
-            .ok_or_else(|| format!("unknown agent id: {agent_id}"))?;
-
-        let root_dir = root_dir
-            .canonicalize()
-            .map_err(|err| format!("invalid root dir: {err}"))?;
-
-        let (session, session_id) = spawn_session(app, agent, root_dir).await?;
-        let session_info = AcpSessionInfo {
-            agent_id: session.agent_id.clone(),
-            session_id: session_id.to_string(),
-        };
+    pub fn sessions(&self) -> Vec<AcpSessionInfo> {
+        self.sessions.values().map(|session| session.info()).collect()
+    }
 
-        self.session = Some(session);
-        Ok(session_info)
+    pub fn session(&self, session_id: &str) -> Result<Arc<AcpSession>, String> {
+        self.sessions
+            .get(session_id)
+            .cloned()
+            .ok_or_else(|| format!("unknown ACP session: {session_id}"))
     }
 
-    pub async fn stop_session(&mut self) -> Result<(), String> {
-        if let Some(mut session) = self.session.take() {
-            session.shutdown().await;
-        }
-        Ok(())
+    pub fn insert_session(&mut self, session: AcpSession) -> AcpSessionInfo {
+        let info = session.info();
+        self.sessions.insert(info.session_id.clone(), Arc::new(session));
+        info
     }
 
-    pub async fn send_prompt(&self, text: String) -> Result<(), String> {
-        let session = self
-            .session
-            .as_ref()
-            .ok_or_else(|| "ACP session not started".to_string())?;
-        session.send_prompt(text).await
+    pub fn remove_session(&mut self, session_id: &str) -> Option<Arc<AcpSession>> {
+        self.sessions.remove(session_id)
     }
 }
 
@@ -117,34 +120,206 @@ impl AcpState {
     }
 }
 
+/// Acknowledgement for a submitted prompt; its outcome arrives later as a turn event.
+#[derive(Debug, Clone, Serialize)]
+pub struct PromptTicket {
+    pub prompt_id: String,
+    /// Number of prompts ahead of this one; zero when it started immediately.
+    pub queue_position: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionStatus {
+    pub running_prompt: Option<String>,
+    pub queued_prompts: Vec<String>,
+}
+
 enum AcpCommand {
     Prompt {
         text: String,
+        respond: oneshot::Sender<PromptTicket>,
+    },
+    Cancel {
         respond: oneshot::Sender<Result<(), String>>,
     },
+    SetMode {
+        mode_id: String,
+        respond: oneshot::Sender<Result<(), String>>,
+    },
+    Status {
+        respond: oneshot::Sender<SessionStatus>,
+    },
     Shutdown,
 }
 
-struct AcpSession {
+pub struct AcpSession {
     agent_id: String,
+    session_id: SessionId,
+    root_dir: PathBuf,
+    client_state: Arc<VisorClientState>,
     child: tokio::sync::Mutex<tokio::process::Child>,
     local_task: JoinHandle<()>,
     command_tx: mpsc::Sender<AcpCommand>,
 }
 
 impl AcpSession {
-    async fn send_prompt(&self, text: String) -> Result<(), String> {
+    pub async fn start(
+        app: AppHandle,
+        agent: AgentConfig,
+        root_dir: PathBuf,
+        staged_writes: bool,
+    ) -> Result<Self, String> {
+        let root_dir = root_dir
+            .canonicalize()
+            .map_err(|err| format!("invalid root dir: {err}"))?;
+        spawn_session(app, agent, root_dir, staged_writes).await
+    }
+
+    pub fn info(&self) -> AcpSessionInfo {
+        AcpSessionInfo {
+            agent_id: self.agent_id.clone(),
+            session_id: self.session_id.to_string(),
+            root_dir: self.root_dir.display().to_string(),
+            staged_writes: self.client_state.staged_writes(),
+        }
+    }
+
+    pub async fn send_prompt(&self, text: String) -> Result<PromptTicket, String> {
+        self.request(|respond| AcpCommand::Prompt { text, respond }).await
+    }
+
+    pub async fn cancel_prompt(&self) -> Result<(), String> {
+        self.request(|respond| AcpCommand::Cancel { respond }).await?
+    }
+
+    pub async fn set_mode(&self, mode_id: String) -> Result<(), String> {
+        self.request(|respond| AcpCommand::SetMode { mode_id, respond }).await?
+    }
+
+    pub async fn status(&self) -> Result<SessionStatus, String> {
+        self.request(|respond| AcpCommand::Status { respond }).await
+    }
+
+    async fn request<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<T>) -> AcpCommand,
+    ) -> Result<T, String> {
         let (tx, rx) = oneshot::channel();
         self.command_tx
-            .send(AcpCommand::Prompt { text, respond: tx })
+            .send(command(tx))
             .await
             .map_err(|_| "ACP command channel closed".to_string())?;
-        rx.await.map_err(|_| "ACP prompt canceled".to_string())?
+        rx.await.map_err(|_| "ACP session stopped".to_string())
+    }
+
+    pub fn respond_permission(&self, request_id: &str, option_id: Option<String>) -> Result<(), String> {
+        self.client_state.permissions.resolve(request_id, option_id)
+    }
+
+    pub fn tool_calls(&self) -> Result<Vec<UiTrackedToolCall>, String> {
+        self.client_state
+            .tool_calls
+            .lock()
+            .map(|store| store.snapshot())
+            .map_err(|_| "tool call store poisoned".to_string())
+    }
+
+    pub fn changes(&self, path: Option<&Path>) -> Result<Vec<UiFileChange>, String> {
+        self.client_state
+            .journal
+            .lock()
+            .map(|journal| journal.changes(path))
+            .map_err(|_| "change journal poisoned".to_string())
+    }
+
+    pub fn checkpoints(&self) -> Result<Vec<UiCheckpoint>, String> {
+        self.client_state
+            .journal
+            .lock()
+            .map(|journal| journal.checkpoints())
+            .map_err(|_| "change journal poisoned".to_string())
     }
 
-    async fn shutdown(&mut self) {
+    pub async fn rollback(&self, scope: RollbackScope) -> Result<Vec<String>, String> {
+        let client_state = self.client_state.clone();
+        let paths = tauri::async_runtime::spawn_blocking(move || client_state.rollback(scope))
+            .await
+            .map_err(|err| format!("rollback task failed: {err}"))??;
+        self.client_state.emit_event(AcpUiEvent::RolledBack {
+            session_id: self.session_id.to_string(),
+            paths: paths.clone(),
+        });
+        Ok(paths)
+    }
+
+    pub fn staged_files(&self) -> Result<Vec<UiStagedFile>, String> {
+        self.client_state.staged_files()
+    }
+
+    pub async fn accept_staged(&self, path: &Path, hunk: Option<usize>) -> Result<(), String> {
+        let (change, file) = self.client_state.accept_staged(path, hunk).await?;
+        let session_id = self.session_id.to_string();
+        self.client_state.emit_event(AcpUiEvent::Staged {
+            session_id: session_id.clone(),
+            path: change.path.clone(),
+            file,
+        });
+        self.client_state
+            .emit_event(AcpUiEvent::FileChanged { session_id, change });
+        Ok(())
+    }
+
+    pub fn reject_staged(&self, path: &Path, hunk: Option<usize>) -> Result<(), String> {
+        let file = self.client_state.reject_staged(path, hunk)?;
+        self.client_state.emit_event(AcpUiEvent::Staged {
+            session_id: self.session_id.to_string(),
+            path: display_relative(&resolve_in_root(path, &self.root_dir), &self.root_dir),
+            file,
+        });
+        Ok(())
+    }
+
+    pub async fn write_terminal(&self, terminal_id: &str, data: &str) -> Result<(), String> {
+        self.client_state.write_terminal(terminal_id, data).await
+    }
+
+    pub async fn resize_terminal(&self, terminal_id: &str, cols: u16, rows: u16) -> Result<(), String> {
+        self.client_state.resize_terminal(terminal_id, cols, rows).await
+    }
+
+    pub async fn kill_terminal(&self, terminal_id: &str) -> Result<(), String> {
+        self.client_state.kill_terminal(terminal_id).await?;
+        self.client_state.emit_event(AcpUiEvent::StatusUpdate {
+            session_id: self.session_id.to_string(),
+            content: format!("Killed terminal {terminal_id}"),
+        });
+        Ok(())
+    }
+
+    pub fn start_recording(&self) {
+        self.client_state.start_recording();
+        self.client_state.emit_event(AcpUiEvent::StatusUpdate {
+            session_id: self.session_id.to_string(),
+            content: "Recording agent terminals to .acp/recordings".to_string(),
+        });
+    }
+
+    pub async fn stop_recording(&self) -> Vec<String> {
+        let paths = self.client_state.stop_recording().await;
+        self.client_state.emit_event(AcpUiEvent::StatusUpdate {
+            session_id: self.session_id.to_string(),
+            content: "Stopped recording agent terminals".to_string(),
+        });
+        paths
+            .into_iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    pub async fn shutdown(&self) {
+        self.client_state.shutdown_terminals().await;
         let _ = self.command_tx.send(AcpCommand::Shutdown).await;
-        let _ = self.local_task.abort();
+        self.local_task.abort();
         let mut child = self.child.lock().await;
         let _ = child.kill().await;
     }
@@ -154,7 +329,12 @@ async fn spawn_session(
     app: AppHandle,
     agent: AgentConfig,
     root_dir: PathBuf,
-) -> Result<(AcpSession, SessionId), String> {
+    staged_writes: bool,
+) -> Result<AcpSession, String> {
+    let policy = PermissionPolicy::load(&root_dir)?;
+    let commands = CommandPolicy::load(&root_dir)?;
+    let sandbox = Sandbox::new(root_dir.clone(), &agent.extra_roots, &agent.denied_paths)?;
+
     let mut command = Command::new(&agent.command);
     command.args(&agent.args);
     command.current_dir(&root_dir);
@@ -179,11 +359,20 @@ async fn spawn_session(
         .take()
         .ok_or_else(|| "agent stdout unavailable".to_string())?;
 
-    let state = Arc::new(VisorClientState::new(root_dir.clone(), app));
-    let handler = VisorClient::new(state);
+    let state = Arc::new(VisorClientState::new(
+        sandbox,
+        policy,
+        commands,
+        staged_writes,
+        agent.max_read_bytes.unwrap_or(DEFAULT_MAX_READ_BYTES),
+        agent.terminal_limits.clone(),
+        app,
+    ));
+    let handler = VisorClient::new(state.clone());
+    let client_state = state.clone();
 
     let (session_tx, session_rx) = oneshot::channel();
-    let (command_tx, mut command_rx) = mpsc::channel::<AcpCommand>(16);
+    let (command_tx, command_rx) = mpsc::channel::<AcpCommand>(16);
 
     let root_dir_for_task = root_dir.clone();
     let local_task = tauri::async_runtime::spawn_blocking(move || {
@@ -201,6 +390,8 @@ async fn spawn_session(
                     tokio::task::spawn_local(task);
                 },
             );
+            // The IO task must be polled for the handshake below to receive any responses.
+            let io_task = tokio::task::spawn_local(io_task);
 
             let init = InitializeRequest::new(ProtocolVersion::LATEST)
                 .client_capabilities(default_client_capabilities());
@@ -223,32 +414,7 @@ async fn spawn_session(
             let session_id = new_session.session_id.clone();
             let _ = session_tx.send(Ok(session_id.clone()));
 
-            tokio::pin!(io_task);
-            loop {
-                tokio::select! {
-                    result = &mut io_task => {
-                        if let Err(err) = result {
-                            eprintln!("ACP IO task error: {err}");
-                        }
-                        break;
-                    }
-                    maybe_cmd = command_rx.recv() => {
-                        let Some(cmd) = maybe_cmd else { break; };
-                        match cmd {
-                            AcpCommand::Prompt { text, respond } => {
-                                let prompt = PromptRequest::new(session_id.clone(), vec![ContentBlock::from(text)]);
-                                let result = client
-                                    .prompt(prompt)
-                                    .await
-                                    .map(|_| ())
-                                    .map_err(|err| format!("prompt failed: {err}"));
-                                let _ = respond.send(result);
-                            }
-                            AcpCommand::Shutdown => break,
-                        }
-                    }
-                }
-            }
+            run_command_loop(Rc::new(client), session_id, client_state, command_rx, io_task).await;
         }));
     });
 
@@ -256,12 +422,154 @@ async fn spawn_session(
         .await
         .map_err(|_| "failed to establish ACP session".to_string())??;
 
-    let session = AcpSession {
+    Ok(AcpSession {
         agent_id: agent.id,
+        session_id,
+        root_dir,
+        client_state: state,
         child: tokio::sync::Mutex::new(child),
         local_task,
         command_tx,
+    })
+}
+
+struct QueuedPrompt {
+    prompt_id: String,
+    text: String,
+}
+
+/// Drives a session after the handshake. Turns run as local tasks and report back over
+/// `turn_done`, so cancel, mode and status commands are served while a turn is in flight;
+/// prompts submitted mid-turn wait in `queue` and start in order.
+async fn run_command_loop(
+    client: Rc<ClientSideConnection>,
+    session_id: SessionId,
+    client_state: Arc<VisorClientState>,
+    mut command_rx: mpsc::Receiver<AcpCommand>,
+    mut io_task: tokio::task::JoinHandle<agent_client_protocol::Result<()>>,
+) {
+    let (turn_done_tx, mut turn_done_rx) = mpsc::unbounded_channel::<()>();
+    let mut queue: VecDeque<QueuedPrompt> = VecDeque::new();
+    let mut running: Option<String> = None;
+    let mut prompt_counter = 0usize;
+
+    let start_turn = |prompt: QueuedPrompt| {
+        client_state.emit_event(AcpUiEvent::TurnStarted {
+            session_id: session_id.to_string(),
+            prompt_id: prompt.prompt_id.clone(),
+        });
+
+        let client = client.clone();
+        let client_state = client_state.clone();
+        let turn_done_tx = turn_done_tx.clone();
+        let request = PromptRequest::new(session_id.clone(), vec![ContentBlock::from(prompt.text)]);
+        let prompt_id = prompt.prompt_id;
+        let session_id = session_id.to_string();
+        tokio::task::spawn_local(async move {
+            client_state.begin_turn(&prompt_id).await;
+            let result = client.prompt(request).await;
+            client_state.end_turn();
+            let event = match result {
+                Ok(response) => AcpUiEvent::TurnEnded {
+                    session_id,
+                    prompt_id,
+                    stop_reason: response.stop_reason,
+                },
+                Err(err) => AcpUiEvent::TurnFailed {
+                    session_id,
+                    prompt_id,
+                    content: format!("prompt failed: {err}"),
+                },
+            };
+            client_state.emit_event(event);
+            let _ = turn_done_tx.send(());
+        });
     };
 
-    Ok((session, session_id))
+    loop {
+        tokio::select! {
+            result = &mut io_task => {
+                match result {
+                    Ok(Err(err)) => eprintln!("ACP IO task error: {err}"),
+                    Err(err) => eprintln!("ACP IO task panicked: {err}"),
+                    Ok(Ok(())) => {}
+                }
+                break;
+            }
+            Some(()) = turn_done_rx.recv() => {
+                running = None;
+                if let Some(next) = queue.pop_front() {
+                    running = Some(next.prompt_id.clone());
+                    start_turn(next);
+                }
+            }
+            maybe_cmd = command_rx.recv() => {
+                let Some(cmd) = maybe_cmd else { break; };
+                match cmd {
+                    AcpCommand::Prompt { text, respond } => {
+                        prompt_counter += 1;
+                        let prompt = QueuedPrompt {
+                            prompt_id: format!("prompt-{prompt_counter}"),
+                            text,
+                        };
+                        let ticket = PromptTicket {
+                            prompt_id: prompt.prompt_id.clone(),
+                            queue_position: queue.len() + usize::from(running.is_some()),
+                        };
+                        if running.is_some() {
+                            client_state.emit_event(AcpUiEvent::PromptQueued {
+                                session_id: session_id.to_string(),
+                                prompt_id: ticket.prompt_id.clone(),
+                                position: ticket.queue_position,
+                            });
+                            queue.push_back(prompt);
+                        } else {
+                            running = Some(prompt.prompt_id.clone());
+                            start_turn(prompt);
+                        }
+                        let _ = respond.send(ticket);
+                    }
+                    AcpCommand::Cancel { respond } => {
+                        // Cancelling stops the running turn and drops everything queued behind it.
+                        for dropped in queue.drain(..) {
+                            client_state.emit_event(AcpUiEvent::TurnEnded {
+                                session_id: session_id.to_string(),
+                                prompt_id: dropped.prompt_id,
+                                stop_reason: StopReason::Cancelled,
+                            });
+                        }
+                        if running.is_none() {
+                            let _ = respond.send(Ok(()));
+                            continue;
+                        }
+                        let result = client
+                            .cancel(CancelNotification::new(session_id.clone()))
+                            .await
+                            .map_err(|err| format!("cancel failed: {err}"));
+                        client_state.cancel_turn().await;
+                        let _ = respond.send(result);
+                    }
+                    AcpCommand::SetMode { mode_id, respond } => {
+                        let client = client.clone();
+                        let request = SetSessionModeRequest::new(session_id.clone(), mode_id);
+                        tokio::task::spawn_local(async move {
+                            let result = client
+                                .set_session_mode(request)
+                                .await
+                                .map(|_| ())
+                                .map_err(|err| format!("set mode failed: {err}"));
+                            let _ = respond.send(result);
+                        });
+                    }
+                    AcpCommand::Status { respond } => {
+                        let _ = respond.send(SessionStatus {
+                            running_prompt: running.clone(),
+                            queued_prompts: queue.iter().map(|prompt| prompt.prompt_id.clone()).collect(),
+                        });
+                    }
+                    AcpCommand::Shutdown => break,
+                }
+            }
+        }
+    }
 }
@@ -1,9 +1,25 @@
+pub mod agent_terminal;
+pub mod command_policy;
 pub mod config;
+pub mod events;
 pub mod handler;
+pub mod journal;
 pub mod manager;
+pub mod overlay;
+pub mod permissions;
+pub mod policy;
+pub mod sandbox;
+pub mod text_file;
+pub mod tool_calls;
 
 use config::{default_config_path, load_agents_config};
-use manager::{AcpManager, AcpSessionInfo, AcpState, AgentSummary};
+use events::{UiCheckpoint, UiFileChange, UiStagedFile, UiTrackedToolCall};
+use journal::RollbackScope;
+use manager::{
+    AcpManager, AcpSession, AcpSessionInfo, AcpState, AgentSummary, PromptTicket, SessionStatus,
+};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tauri::{AppHandle, State};
 
 pub fn init_state() -> AcpState {
@@ -13,10 +29,21 @@ pub fn init_state() -> AcpState {
 pub async fn load_config_into_state(state: &AcpState) -> Result<(), String> {
     let config = load_agents_config(&state.config_path)?;
     let mut guard = state.manager.write().await;
-    *guard = Some(AcpManager::new(config));
+    match guard.as_mut() {
+        Some(manager) => manager.replace_config(config)?,
+        None => *guard = Some(AcpManager::new(config)),
+    }
     Ok(())
 }
 
+async fn find_session(state: &AcpState, session_id: &str) -> Result<Arc<AcpSession>, String> {
+    let guard = state.manager.read().await;
+    guard
+        .as_ref()
+        .ok_or_else(|| "ACP configuration not loaded".to_string())?
+        .session(session_id)
+}
+
 #[tauri::command]
 pub async fn acp_list_agents(state: State<'_, AcpState>) -> Result<Vec<AgentSummary>, String> {
     let guard = state.manager.read().await;
@@ -30,45 +57,260 @@ pub async fn acp_list_agents(state: State<'_, AcpState>) -> Result<Vec<AgentSumm
 pub async fn acp_reload_config(
     state: State<'_, AcpState>,
 ) -> Result<Vec<AgentSummary>, String> {
-    if let Some(manager) = state.manager.read().await.as_ref() {
-        if manager.session_active() {
-            return Err("cannot reload config while ACP session is active".to_string());
-        }
-    }
     load_config_into_state(&state).await?;
     acp_list_agents(state).await
 }
 
+#[tauri::command]
+pub async fn acp_list_sessions(state: State<'_, AcpState>) -> Result<Vec<AcpSessionInfo>, String> {
+    let guard = state.manager.read().await;
+    guard
+        .as_ref()
+        .map(|manager| manager.sessions())
+        .ok_or_else(|| "ACP configuration not loaded".to_string())
+}
+
 #[tauri::command]
 pub async fn acp_start_session(
     agent_id: String,
     root_dir: String,
+    staged_writes: Option<bool>,
     app: AppHandle,
     state: State<'_, AcpState>,
 ) -> Result<AcpSessionInfo, String> {
+    let agent = {
+        let guard = state.manager.read().await;
+        guard
+            .as_ref()
+            .ok_or_else(|| "ACP configuration not loaded".to_string())?
+            .find_agent(&agent_id)
+            .ok_or_else(|| format!("unknown agent id: {agent_id}"))?
+    };
+
+    let session =
+        AcpSession::start(app, agent, root_dir.into(), staged_writes.unwrap_or(false)).await?;
+
     let mut guard = state.manager.write().await;
     let manager = guard
         .as_mut()
         .ok_or_else(|| "ACP configuration not loaded".to_string())?;
-    manager
-        .start_session(app, agent_id, root_dir.into())
+    Ok(manager.insert_session(session))
+}
+
+#[tauri::command]
+pub async fn acp_stop_session(session_id: String, state: State<'_, AcpState>) -> Result<(), String> {
+    let session = {
+        let mut guard = state.manager.write().await;
+        let manager = guard
+            .as_mut()
+            .ok_or_else(|| "ACP configuration not loaded".to_string())?;
+        manager.remove_session(&session_id)
+    };
+    if let Some(session) = session {
+        session.shutdown().await;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn acp_send_prompt(
+    session_id: String,
+    text: String,
+    state: State<'_, AcpState>,
+) -> Result<PromptTicket, String> {
+    let session = find_session(&state, &session_id).await?;
+    session.send_prompt(text).await
+}
+
+#[tauri::command]
+pub async fn acp_cancel_prompt(session_id: String, state: State<'_, AcpState>) -> Result<(), String> {
+    let session = find_session(&state, &session_id).await?;
+    session.cancel_prompt().await
+}
+
+#[tauri::command]
+pub async fn acp_respond_permission(
+    session_id: String,
+    request_id: String,
+    option_id: Option<String>,
+    state: State<'_, AcpState>,
+) -> Result<(), String> {
+    let session = find_session(&state, &session_id).await?;
+    session.respond_permission(&request_id, option_id)
+}
+
+#[tauri::command]
+pub async fn acp_set_session_mode(
+    session_id: String,
+    mode_id: String,
+    state: State<'_, AcpState>,
+) -> Result<(), String> {
+    let session = find_session(&state, &session_id).await?;
+    session.set_mode(mode_id).await
+}
+
+#[tauri::command]
+pub async fn acp_session_status(
+    session_id: String,
+    state: State<'_, AcpState>,
+) -> Result<SessionStatus, String> {
+    let session = find_session(&state, &session_id).await?;
+    session.status().await
+}
+
+#[tauri::command]
+pub async fn acp_tool_calls(
+    session_id: String,
+    state: State<'_, AcpState>,
+) -> Result<Vec<UiTrackedToolCall>, String> {
+    let session = find_session(&state, &session_id).await?;
+    session.tool_calls()
+}
+
+/// The session's change journal, optionally limited to one file (absolute or root-relative).
+#[tauri::command]
+pub async fn acp_session_changes(
+    session_id: String,
+    path: Option<String>,
+    state: State<'_, AcpState>,
+) -> Result<Vec<UiFileChange>, String> {
+    let session = find_session(&state, &session_id).await?;
+    session.changes(path.as_deref().map(Path::new))
+}
+
+#[tauri::command]
+pub async fn acp_list_checkpoints(
+    session_id: String,
+    state: State<'_, AcpState>,
+) -> Result<Vec<UiCheckpoint>, String> {
+    let session = find_session(&state, &session_id).await?;
+    session.checkpoints()
+}
+
+/// Reverts every agent change since the checkpoint taken when `prompt_id` started,
+/// including later turns. Returns the restored paths.
+#[tauri::command]
+pub async fn acp_rollback_session(
+    session_id: String,
+    prompt_id: String,
+    state: State<'_, AcpState>,
+) -> Result<Vec<String>, String> {
+    let session = find_session(&state, &session_id).await?;
+    session.rollback(RollbackScope::Since(prompt_id)).await
+}
+
+/// Reverts only the changes made during the `prompt_id` turn.
+#[tauri::command]
+pub async fn acp_rollback_turn(
+    session_id: String,
+    prompt_id: String,
+    state: State<'_, AcpState>,
+) -> Result<Vec<String>, String> {
+    let session = find_session(&state, &session_id).await?;
+    session.rollback(RollbackScope::Turn(prompt_id)).await
+}
+
+/// Restores one file to its state at a checkpoint, or at session start without `prompt_id`.
+#[tauri::command]
+pub async fn acp_rollback_file(
+    session_id: String,
+    path: String,
+    prompt_id: Option<String>,
+    state: State<'_, AcpState>,
+) -> Result<Vec<String>, String> {
+    let session = find_session(&state, &session_id).await?;
+    session
+        .rollback(RollbackScope::File {
+            path: PathBuf::from(path),
+            since: prompt_id,
+        })
         .await
 }
 
 #[tauri::command]
-pub async fn acp_stop_session(state: State<'_, AcpState>) -> Result<(), String> {
-    let mut guard = state.manager.write().await;
-    let manager = guard
-        .as_mut()
-        .ok_or_else(|| "ACP configuration not loaded".to_string())?;
-    manager.stop_session().await
+pub async fn acp_staged_changes(
+    session_id: String,
+    state: State<'_, AcpState>,
+) -> Result<Vec<UiStagedFile>, String> {
+    let session = find_session(&state, &session_id).await?;
+    session.staged_files()
 }
 
+/// Writes a staged file to disk, or only hunk `hunk` of it.
 #[tauri::command]
-pub async fn acp_send_prompt(text: String, state: State<'_, AcpState>) -> Result<(), String> {
-    let guard = state.manager.read().await;
-    let manager = guard
-        .as_ref()
-        .ok_or_else(|| "ACP configuration not loaded".to_string())?;
-    manager.send_prompt(text).await
+pub async fn acp_accept_staged(
+    session_id: String,
+    path: String,
+    hunk: Option<usize>,
+    state: State<'_, AcpState>,
+) -> Result<(), String> {
+    let session = find_session(&state, &session_id).await?;
+    session.accept_staged(Path::new(&path), hunk).await
+}
+
+/// Discards a staged file, or only hunk `hunk` of it.
+#[tauri::command]
+pub async fn acp_reject_staged(
+    session_id: String,
+    path: String,
+    hunk: Option<usize>,
+    state: State<'_, AcpState>,
+) -> Result<(), String> {
+    let session = find_session(&state, &session_id).await?;
+    session.reject_staged(Path::new(&path), hunk)
+}
+
+/// Sends user input to a terminal the agent created, e.g. to answer a prompt.
+#[tauri::command]
+pub async fn acp_write_terminal(
+    session_id: String,
+    terminal_id: String,
+    data: String,
+    state: State<'_, AcpState>,
+) -> Result<(), String> {
+    let session = find_session(&state, &session_id).await?;
+    session.write_terminal(&terminal_id, &data).await
+}
+
+#[tauri::command]
+pub async fn acp_resize_terminal(
+    session_id: String,
+    terminal_id: String,
+    cols: u16,
+    rows: u16,
+    state: State<'_, AcpState>,
+) -> Result<(), String> {
+    let session = find_session(&state, &session_id).await?;
+    session.resize_terminal(&terminal_id, cols, rows).await
+}
+
+#[tauri::command]
+pub async fn acp_kill_terminal(
+    session_id: String,
+    terminal_id: String,
+    state: State<'_, AcpState>,
+) -> Result<(), String> {
+    let session = find_session(&state, &session_id).await?;
+    session.kill_terminal(&terminal_id).await
+}
+
+/// Records the agent terminals this session creates from now on.
+#[tauri::command]
+pub async fn acp_start_recording(
+    session_id: String,
+    state: State<'_, AcpState>,
+) -> Result<(), String> {
+    let session = find_session(&state, &session_id).await?;
+    session.start_recording();
+    Ok(())
+}
+
+/// Stops recording and returns the files of the recordings it ended.
+#[tauri::command]
+pub async fn acp_stop_recording(
+    session_id: String,
+    state: State<'_, AcpState>,
+) -> Result<Vec<String>, String> {
+    let session = find_session(&state, &session_id).await?;
+    Ok(session.stop_recording().await)
 }
@@ -0,0 +1,244 @@
+use crate::acp::events::{UiHunk, UiStagedFile};
+use crate::acp::tool_calls::display_relative;
+use similar::TextDiff;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// The latest content an agent wrote to a file while writes were being staged.
+#[derive(Clone)]
+pub struct StagedWrite {
+    pub content: String,
+    pub tool_call_id: Option<String>,
+    pub prompt_id: Option<String>,
+}
+
+/// In-memory layer over the project tree for sessions in staged-write mode. Agent writes land
+/// here instead of on disk, agent reads see them, and nothing reaches the real tree until the
+/// user accepts it.
+pub struct StagedOverlay {
+    root_dir: PathBuf,
+    files: HashMap<PathBuf, StagedWrite>,
+    order: Vec<PathBuf>,
+}
+
+/// A run of changed lines between the file on disk and its staged content.
+struct Hunk {
+    old: Range<usize>,
+    new: Range<usize>,
+}
+
+impl StagedOverlay {
+    pub fn new(root_dir: PathBuf) -> Self {
+        Self {
+            root_dir,
+            files: HashMap::new(),
+            order: Vec::new(),
+        }
+    }
+
+    pub fn stage(&mut self, path: PathBuf, write: StagedWrite) -> UiStagedFile {
+        if !self.files.contains_key(&path) {
+            self.order.push(path.clone());
+        }
+        let file = self.to_ui(&path, &write);
+        self.files.insert(path, write);
+        file
+    }
+
+    pub fn read(&self, path: &Path) -> Option<&str> {
+        self.files.get(path).map(|write| write.content.as_str())
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&StagedWrite> {
+        self.files.get(path)
+    }
+
+    pub fn file(&self, path: &Path) -> Option<UiStagedFile> {
+        self.files.get(path).map(|write| self.to_ui(path, write))
+    }
+
+    pub fn files(&self) -> Vec<UiStagedFile> {
+        self.order
+            .iter()
+            .filter_map(|path| self.file(path))
+            .collect()
+    }
+
+    pub fn remove(&mut self, path: &Path) -> Option<StagedWrite> {
+        self.order.retain(|staged| staged != path);
+        self.files.remove(path)
+    }
+
+    /// The file content that results from applying one staged hunk to what is on disk.
+    pub fn accept_hunk(&self, path: &Path, index: usize) -> Result<String, String> {
+        let staged = self.staged(path)?;
+        let disk = read_disk(path)?.unwrap_or_default();
+        let hunk = pick(&disk, &staged.content, index)?;
+        Ok(splice(&disk, hunk.old, &staged.content, hunk.new))
+    }
+
+    /// Drops one hunk from the staged content. The file leaves the overlay once nothing
+    /// staged differs from disk.
+    pub fn reject_hunk(&mut self, path: &Path, index: usize) -> Result<(), String> {
+        let disk = read_disk(path)?;
+        let base = disk.as_deref().unwrap_or_default();
+        let staged = self.staged(path)?;
+        let hunk = pick(base, &staged.content, index)?;
+        let content = splice(&staged.content, hunk.new, base, hunk.old);
+        self.settle(path, content, disk.as_deref());
+        Ok(())
+    }
+
+    /// Keeps `content` staged for `path` unless it matches what is on disk.
+    pub fn settle(&mut self, path: &Path, content: String, disk: Option<&str>) {
+        let unchanged = match disk {
+            Some(disk) => disk == content,
+            None => content.is_empty(),
+        };
+        if unchanged {
+            self.remove(path);
+        } else if let Some(write) = self.files.get_mut(path) {
+            write.content = content;
+        }
+    }
+
+    fn staged(&self, path: &Path) -> Result<&StagedWrite, String> {
+        self.files.get(path).ok_or_else(|| {
+            format!(
+                "no staged changes for {}",
+                display_relative(path, &self.root_dir)
+            )
+        })
+    }
+
+    fn to_ui(&self, path: &Path, write: &StagedWrite) -> UiStagedFile {
+        let disk = std::fs::read(path).ok();
+        let base = disk
+            .as_ref()
+            .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+            .unwrap_or_default();
+        let old_lines: Vec<&str> = base.split_inclusive('\n').collect();
+        let new_lines: Vec<&str> = write.content.split_inclusive('\n').collect();
+        let hunks = hunks(&base, &write.content)
+            .into_iter()
+            .enumerate()
+            .map(|(index, hunk)| UiHunk {
+                index,
+                old_start: hunk.old.start + 1,
+                old_lines: hunk.old.len(),
+                new_start: hunk.new.start + 1,
+                new_lines: hunk.new.len(),
+                removed: old_lines[hunk.old].concat(),
+                added: new_lines[hunk.new].concat(),
+            })
+            .collect();
+        UiStagedFile {
+            path: display_relative(path, &self.root_dir),
+            created: disk.is_none(),
+            tool_call_id: write.tool_call_id.clone(),
+            prompt_id: write.prompt_id.clone(),
+            hunks,
+        }
+    }
+}
+
+/// Reads the on-disk side of a staged file; `None` if the agent is creating it.
+fn read_disk(path: &Path) -> Result<Option<String>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => Ok(Some(text)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(format!("failed to read {}: {err}", path.display())),
+    }
+}
+
+fn hunks(old: &str, new: &str) -> Vec<Hunk> {
+    let diff = TextDiff::from_lines(old, new);
+    diff.grouped_ops(0)
+        .into_iter()
+        .filter_map(|group| {
+            let first = group.first()?;
+            let last = group.last()?;
+            Some(Hunk {
+                old: first.old_range().start..last.old_range().end,
+                new: first.new_range().start..last.new_range().end,
+            })
+        })
+        .filter(|hunk| !hunk.old.is_empty() || !hunk.new.is_empty())
+        .collect()
+}
+
+fn pick(old: &str, new: &str, index: usize) -> Result<Hunk, String> {
+    hunks(old, new)
+        .into_iter()
+        .nth(index)
+        .ok_or_else(|| format!("unknown hunk: {index}"))
+}
+
+/// `base` with the lines in `base_range` replaced by the lines in `other_range` of `other`.
+fn splice(base: &str, base_range: Range<usize>, other: &str, other_range: Range<usize>) -> String {
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let other: Vec<&str> = other.split_inclusive('\n').collect();
+    let mut lines = base[..base_range.start].to_vec();
+    lines.extend_from_slice(&other[other_range]);
+    lines.extend_from_slice(&base[base_range.end..]);
+    lines.concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn staged(content: &str) -> StagedWrite {
+        StagedWrite {
+            content: content.to_string(),
+            tool_call_id: None,
+            prompt_id: None,
+        }
+    }
+
+    #[test]
+    fn hunks_can_be_accepted_and_rejected_independently() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "one\ntwo\nthree\nfour\n").unwrap();
+        let mut overlay = StagedOverlay::new(dir.path().to_path_buf());
+
+        let file = overlay.stage(path.clone(), staged("ONE\ntwo\nthree\nFOUR\nfive\n"));
+        assert!(!file.created);
+        assert_eq!(file.hunks.len(), 2);
+        assert_eq!(file.hunks[0].removed, "one\n");
+        assert_eq!(file.hunks[1].added, "FOUR\nfive\n");
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "one\ntwo\nthree\nfour\n"
+        );
+
+        let accepted = overlay.accept_hunk(&path, 0).unwrap();
+        assert_eq!(accepted, "ONE\ntwo\nthree\nfour\n");
+        std::fs::write(&path, &accepted).unwrap();
+        let staged_content = overlay.read(&path).unwrap().to_string();
+        overlay.settle(&path, staged_content, Some(&accepted));
+        assert_eq!(overlay.file(&path).unwrap().hunks.len(), 1);
+
+        overlay.reject_hunk(&path, 0).unwrap();
+        assert!(overlay.read(&path).is_none());
+        assert!(overlay.files().is_empty());
+    }
+
+    #[test]
+    fn created_files_are_readable_before_they_exist_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("new.rs");
+        let mut overlay = StagedOverlay::new(dir.path().to_path_buf());
+
+        let file = overlay.stage(path.clone(), staged("fn main() {}\n"));
+        assert!(file.created);
+        assert_eq!(file.path, "new.rs");
+        assert_eq!(overlay.read(&path), Some("fn main() {}\n"));
+        assert!(!path.exists());
+
+        overlay.reject_hunk(&path, 0).unwrap();
+        assert!(overlay.files().is_empty());
+    }
+}
@@ -0,0 +1,206 @@
+use agent_client_protocol::{
+    PermissionOption, PermissionOptionId, RequestPermissionOutcome, SelectedPermissionOutcome,
+};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// How long an agent's permission request waits for the user before it is cancelled.
+pub const PERMISSION_TIMEOUT: Duration = Duration::from_secs(300);
+
+struct PendingPermission {
+    option_ids: Vec<PermissionOptionId>,
+    respond: oneshot::Sender<RequestPermissionOutcome>,
+}
+
+/// Tracks permission requests that have been forwarded to the UI and are awaiting a choice.
+pub struct PermissionBroker {
+    pending: Mutex<HashMap<String, PendingPermission>>,
+    counter: AtomicUsize,
+}
+
+impl PermissionBroker {
+    pub fn new() -> Self {
+        Self {
+            pending: Mutex::new(HashMap::new()),
+            counter: AtomicUsize::new(1),
+        }
+    }
+
+    pub fn register(
+        &self,
+        options: &[PermissionOption],
+    ) -> (String, oneshot::Receiver<RequestPermissionOutcome>) {
+        let next = self.counter.fetch_add(1, Ordering::SeqCst);
+        let request_id = format!("perm-{next}");
+        let (tx, rx) = oneshot::channel();
+        let pending = PendingPermission {
+            option_ids: options
+                .iter()
+                .map(|option| option.option_id.clone())
+                .collect(),
+            respond: tx,
+        };
+        if let Ok(mut guard) = self.pending.lock() {
+            guard.insert(request_id.clone(), pending);
+        }
+        (request_id, rx)
+    }
+
+    /// Resolves a pending request with the user's choice; `None` cancels it.
+    pub fn resolve(&self, request_id: &str, option_id: Option<String>) -> Result<(), String> {
+        let mut guard = self
+            .pending
+            .lock()
+            .map_err(|_| "permission mutex poisoned".to_string())?;
+        let pending = guard
+            .get(request_id)
+            .ok_or_else(|| format!("unknown permission request: {request_id}"))?;
+
+        let outcome = match option_id {
+            Some(option_id) => {
+                let option_id = PermissionOptionId::new(option_id);
+                if !pending.option_ids.contains(&option_id) {
+                    return Err(format!("unknown permission option: {option_id}"));
+                }
+                RequestPermissionOutcome::Selected(SelectedPermissionOutcome::new(option_id))
+            }
+            None => RequestPermissionOutcome::Cancelled,
+        };
+
+        if let Some(pending) = guard.remove(request_id) {
+            let _ = pending.respond.send(outcome);
+        }
+        Ok(())
+    }
+
+    /// Answers every pending request with `Cancelled`, as required when a turn is cancelled.
+    pub fn cancel_all(&self) {
+        let drained: Vec<PendingPermission> = match self.pending.lock() {
+            Ok(mut guard) => guard.drain().map(|(_, pending)| pending).collect(),
+            Err(_) => return,
+        };
+        for pending in drained {
+            let _ = pending.respond.send(RequestPermissionOutcome::Cancelled);
+        }
+    }
+
+    /// Drops a pending request without answering it, e.g. after it timed out.
+    pub fn discard(&self, request_id: &str) {
+        if let Ok(mut guard) = self.pending.lock() {
+            guard.remove(request_id);
+        }
+    }
+
+    /// Waits up to `timeout` for the user's answer to `request_id`. A request that times
+    /// out, or whose answer can no longer arrive, is discarded and counts as `Cancelled`.
+    pub async fn wait(
+        &self,
+        request_id: &str,
+        response: oneshot::Receiver<RequestPermissionOutcome>,
+        timeout: Duration,
+    ) -> RequestPermissionOutcome {
+        match tokio::time::timeout(timeout, response).await {
+            Ok(Ok(outcome)) => outcome,
+            _ => {
+                self.discard(request_id);
+                RequestPermissionOutcome::Cancelled
+            }
+        }
+    }
+}
+
+impl Default for PermissionBroker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agent_client_protocol::PermissionOptionKind;
+
+    fn options() -> Vec<PermissionOption> {
+        vec![
+            PermissionOption::new("allow", "Allow", PermissionOptionKind::AllowOnce),
+            PermissionOption::new("reject", "Reject", PermissionOptionKind::RejectOnce),
+        ]
+    }
+
+    fn selected(outcome: RequestPermissionOutcome) -> Option<String> {
+        match outcome {
+            RequestPermissionOutcome::Selected(selected) => Some(selected.option_id.to_string()),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_are_answered_once_with_an_offered_option() {
+        let broker = PermissionBroker::new();
+        let (request_id, response) = broker.register(&options());
+
+        let err = broker
+            .resolve(&request_id, Some("always".to_string()))
+            .unwrap_err();
+        assert!(err.contains("unknown permission option"), "{err}");
+        let err = broker.resolve("perm-999", None).unwrap_err();
+        assert!(err.contains("unknown permission request"), "{err}");
+
+        broker
+            .resolve(&request_id, Some("reject".to_string()))
+            .unwrap();
+        let err = broker
+            .resolve(&request_id, Some("allow".to_string()))
+            .unwrap_err();
+        assert!(err.contains("unknown permission request"), "{err}");
+        assert_eq!(selected(response.await.unwrap()).as_deref(), Some("reject"));
+    }
+
+    #[tokio::test]
+    async fn cancel_all_answers_every_waiter_with_cancelled() {
+        let broker = PermissionBroker::new();
+        let (first_id, first) = broker.register(&options());
+        let (_, second) = broker.register(&options());
+
+        broker.cancel_all();
+        assert!(matches!(
+            first.await,
+            Ok(RequestPermissionOutcome::Cancelled)
+        ));
+        assert!(matches!(
+            second.await,
+            Ok(RequestPermissionOutcome::Cancelled)
+        ));
+        assert!(broker.resolve(&first_id, None).is_err());
+    }
+
+    #[tokio::test]
+    async fn discarded_and_timed_out_requests_are_forgotten() {
+        let broker = PermissionBroker::new();
+        let (request_id, response) = broker.register(&options());
+        broker.discard(&request_id);
+        assert!(response.await.is_err());
+        assert!(broker.resolve(&request_id, None).is_err());
+
+        let (request_id, response) = broker.register(&options());
+        let outcome = broker
+            .wait(&request_id, response, Duration::from_millis(10))
+            .await;
+        assert!(matches!(outcome, RequestPermissionOutcome::Cancelled));
+        assert!(broker
+            .resolve(&request_id, Some("allow".to_string()))
+            .is_err());
+
+        let (request_id, response) = broker.register(&options());
+        broker
+            .resolve(&request_id, Some("allow".to_string()))
+            .unwrap();
+        let outcome = broker
+            .wait(&request_id, response, Duration::from_secs(5))
+            .await;
+        assert_eq!(selected(outcome).as_deref(), Some("allow"));
+    }
+}
@@ -0,0 +1,533 @@
+use agent_client_protocol::{
+    PermissionOption, PermissionOptionKind, RequestPermissionOutcome, SelectedPermissionOutcome,
+    ToolCallUpdate, ToolKind,
+};
+use globset::{Glob, GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+
+pub const PERMISSION_POLICY_PATH: &str = ".acp/permissions.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyDecision {
+    Allow,
+    Deny,
+    Ask,
+}
+
+impl PolicyDecision {
+    /// Picks the permission option that carries out this decision, preferring one-shot options
+    /// so that the agent does not remember anything on our behalf. A denial the agent offered
+    /// no reject option for is answered as cancelled, so a deny rule never becomes a prompt.
+    pub fn select(self, options: &[PermissionOption]) -> Option<RequestPermissionOutcome> {
+        let preferred: &[PermissionOptionKind] = match self {
+            PolicyDecision::Allow => &[
+                PermissionOptionKind::AllowOnce,
+                PermissionOptionKind::AllowAlways,
+            ],
+            PolicyDecision::Deny => &[
+                PermissionOptionKind::RejectOnce,
+                PermissionOptionKind::RejectAlways,
+            ],
+            PolicyDecision::Ask => return None,
+        };
+        let selected = preferred.iter().find_map(|kind| {
+            options
+                .iter()
+                .find(|option| option.kind == *kind)
+                .map(|option| {
+                    RequestPermissionOutcome::Selected(SelectedPermissionOutcome::new(
+                        option.option_id.clone(),
+                    ))
+                })
+        });
+        match (self, selected) {
+            (PolicyDecision::Deny, None) => Some(RequestPermissionOutcome::Cancelled),
+            (_, selected) => selected,
+        }
+    }
+
+    /// Maps a remembered user choice to the decision it implies for future requests.
+    pub fn from_remembered_kind(kind: PermissionOptionKind) -> Option<Self> {
+        match kind {
+            PermissionOptionKind::AllowAlways => Some(PolicyDecision::Allow),
+            PermissionOptionKind::RejectAlways => Some(PolicyDecision::Deny),
+            _ => None,
+        }
+    }
+}
+
+/// A single policy rule. Every criterion that is set must match; an empty rule matches
+/// every tool call.
+///
+/// `paths` are globs relative to the session root. An `allow` rule only matches when every
+/// location touched by the tool call matches one of its globs, while `deny` and `ask` rules
+/// match as soon as any location does.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PolicyRule {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kinds: Vec<ToolKind>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<String>,
+    pub decision: PolicyDecision,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PolicyFile {
+    #[serde(default)]
+    pub rules: Vec<PolicyRule>,
+    #[serde(default)]
+    pub remembered: Vec<PolicyRule>,
+}
+
+struct CompiledRule {
+    rule: PolicyRule,
+    title: Option<GlobMatcher>,
+    paths: Option<GlobSet>,
+}
+
+impl CompiledRule {
+    fn compile(rule: PolicyRule) -> Result<Self, String> {
+        let title = rule
+            .title
+            .as_deref()
+            .map(|pattern| {
+                Glob::new(pattern)
+                    .map(|glob| glob.compile_matcher())
+                    .map_err(|err| format!("invalid title pattern {pattern:?}: {err}"))
+            })
+            .transpose()?;
+
+        let paths = if rule.paths.is_empty() {
+            None
+        } else {
+            let mut builder = GlobSetBuilder::new();
+            for pattern in &rule.paths {
+                let glob = GlobBuilder::new(pattern)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|err| format!("invalid path pattern {pattern:?}: {err}"))?;
+                builder.add(glob);
+            }
+            Some(
+                builder
+                    .build()
+                    .map_err(|err| format!("invalid path patterns: {err}"))?,
+            )
+        };
+
+        Ok(Self { rule, title, paths })
+    }
+
+    fn matches(&self, tool_call: &ToolCallUpdate, paths: &[Option<PathBuf>]) -> bool {
+        let fields = &tool_call.fields;
+
+        if !self.rule.kinds.is_empty() {
+            match fields.kind {
+                Some(kind) if self.rule.kinds.contains(&kind) => {}
+                _ => return false,
+            }
+        }
+
+        if let Some(title) = &self.title {
+            match fields.title.as_deref() {
+                Some(value) if title.is_match(value) => {}
+                _ => return false,
+            }
+        }
+
+        if let Some(globs) = &self.paths {
+            if paths.is_empty() {
+                return false;
+            }
+            let matched =
+                |path: &Option<PathBuf>| path.as_ref().is_some_and(|path| globs.is_match(path));
+            let matches = match self.rule.decision {
+                PolicyDecision::Allow => paths.iter().all(matched),
+                PolicyDecision::Deny | PolicyDecision::Ask => paths.iter().any(matched),
+            };
+            if !matches {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Decides permission requests from project rules and remembered "always" choices.
+///
+/// Configured rules are checked in order and the first `allow` or `deny` match wins. A matching
+/// `ask` rule stops the scan but still lets remembered choices apply; anything left over is
+/// asked.
+pub struct PermissionPolicy {
+    root_dir: PathBuf,
+    file: PolicyFile,
+    rules: Vec<CompiledRule>,
+    remembered: Vec<CompiledRule>,
+}
+
+impl PermissionPolicy {
+    pub fn new(root_dir: PathBuf, file: PolicyFile) -> Result<Self, String> {
+        let rules = compile_rules(&file.rules)?;
+        let remembered = compile_rules(&file.remembered)?;
+        Ok(Self {
+            root_dir,
+            file,
+            rules,
+            remembered,
+        })
+    }
+
+    /// Loads `.acp/permissions.json` from the project root; a missing file yields an empty policy.
+    pub fn load(root_dir: &Path) -> Result<Self, String> {
+        let path = root_dir.join(PERMISSION_POLICY_PATH);
+        let file = if path.exists() {
+            let raw = std::fs::read_to_string(&path).map_err(|err| {
+                format!("failed to read permission policy {}: {err}", path.display())
+            })?;
+            serde_json::from_str(&raw).map_err(|err| {
+                format!(
+                    "failed to parse permission policy {}: {err}",
+                    path.display()
+                )
+            })?
+        } else {
+            PolicyFile::default()
+        };
+        Self::new(root_dir.to_path_buf(), file)
+    }
+
+    pub fn evaluate(&self, tool_call: &ToolCallUpdate) -> PolicyDecision {
+        let paths = self.relative_paths(tool_call);
+
+        for rule in &self.rules {
+            if !rule.matches(tool_call, &paths) {
+                continue;
+            }
+            match rule.rule.decision {
+                PolicyDecision::Ask => break,
+                decision => return decision,
+            }
+        }
+
+        self.remembered
+            .iter()
+            .find(|rule| rule.matches(tool_call, &paths))
+            .map(|rule| rule.rule.decision)
+            .unwrap_or(PolicyDecision::Ask)
+    }
+
+    /// Records an "always" choice for tool calls with the same kind and title and persists it.
+    /// Returns `false` when the tool call has no title to key the decision on.
+    pub fn remember(
+        &mut self,
+        tool_call: &ToolCallUpdate,
+        decision: PolicyDecision,
+    ) -> Result<bool, String> {
+        let Some(title) = tool_call.fields.title.as_deref() else {
+            return Ok(false);
+        };
+        let rule = PolicyRule {
+            kinds: tool_call.fields.kind.into_iter().collect(),
+            title: Some(globset::escape(title)),
+            paths: Vec::new(),
+            decision,
+        };
+        if self.file.remembered.contains(&rule) {
+            return Ok(true);
+        }
+
+        self.remembered.push(CompiledRule::compile(rule.clone())?);
+        self.file.remembered.push(rule);
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Location paths relative to the project root, or `None` for a path outside it. Paths
+    /// are normalized lexically first, so `src/../../etc` cannot pass for a `src/**` match;
+    /// a `None` never satisfies an `allow` path rule.
+    fn relative_paths(&self, tool_call: &ToolCallUpdate) -> Vec<Option<PathBuf>> {
+        let root_dir = normalize_lexically(&self.root_dir);
+        tool_call
+            .fields
+            .locations
+            .iter()
+            .flatten()
+            .map(|location| {
+                let path = normalize_lexically(&location.path);
+                let relative = if path.is_absolute() {
+                    path.strip_prefix(&root_dir).ok()?.to_path_buf()
+                } else {
+                    path
+                };
+                if relative
+                    .components()
+                    .any(|component| matches!(component, Component::ParentDir))
+                {
+                    return None;
+                }
+                Some(relative)
+            })
+            .collect()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = self.root_dir.join(PERMISSION_POLICY_PATH);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| format!("failed to create {}: {err}", parent.display()))?;
+        }
+        let raw = serde_json::to_string_pretty(&self.file)
+            .map_err(|err| format!("failed to serialize permission policy: {err}"))?;
+        std::fs::write(&path, raw).map_err(|err| {
+            format!(
+                "failed to write permission policy {}: {err}",
+                path.display()
+            )
+        })
+    }
+}
+
+/// Resolves `.` and `..` without touching the filesystem. A `..` that would climb above
+/// the start of a relative path is kept; one above the root of an absolute path is dropped.
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => normalized.push(Component::ParentDir),
+            },
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn compile_rules(rules: &[PolicyRule]) -> Result<Vec<CompiledRule>, String> {
+    rules.iter().cloned().map(CompiledRule::compile).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agent_client_protocol::{ToolCallLocation, ToolCallUpdateFields};
+    use tempfile::TempDir;
+
+    fn tool_call(kind: ToolKind, title: &str, paths: &[&Path]) -> ToolCallUpdate {
+        let locations: Vec<_> = paths
+            .iter()
+            .map(|path| ToolCallLocation::new(*path))
+            .collect();
+        ToolCallUpdate::new(
+            "call-1",
+            ToolCallUpdateFields::new()
+                .kind(kind)
+                .title(title.to_string())
+                .locations(locations),
+        )
+    }
+
+    fn rule(
+        kinds: &[ToolKind],
+        title: Option<&str>,
+        paths: &[&str],
+        decision: PolicyDecision,
+    ) -> PolicyRule {
+        PolicyRule {
+            kinds: kinds.to_vec(),
+            title: title.map(str::to_string),
+            paths: paths.iter().map(|p| p.to_string()).collect(),
+            decision,
+        }
+    }
+
+    fn policy(root: &Path, rules: Vec<PolicyRule>) -> PermissionPolicy {
+        let file = PolicyFile {
+            rules,
+            remembered: Vec::new(),
+        };
+        PermissionPolicy::new(root.to_path_buf(), file).unwrap()
+    }
+
+    #[test]
+    fn unmatched_requests_are_asked() {
+        let temp = TempDir::new().unwrap();
+        let policy = policy(
+            temp.path(),
+            vec![rule(&[ToolKind::Read], None, &[], PolicyDecision::Allow)],
+        );
+        let call = tool_call(ToolKind::Execute, "Run ls", &[]);
+        assert_eq!(policy.evaluate(&call), PolicyDecision::Ask);
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let temp = TempDir::new().unwrap();
+        let policy = policy(
+            temp.path(),
+            vec![
+                rule(&[], Some("Run git push*"), &[], PolicyDecision::Deny),
+                rule(&[ToolKind::Execute], None, &[], PolicyDecision::Allow),
+            ],
+        );
+        let push = tool_call(ToolKind::Execute, "Run git push origin main", &[]);
+        let status = tool_call(ToolKind::Execute, "Run git status", &[]);
+        assert_eq!(policy.evaluate(&push), PolicyDecision::Deny);
+        assert_eq!(policy.evaluate(&status), PolicyDecision::Allow);
+    }
+
+    #[test]
+    fn allow_rules_require_every_path_to_match() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        let policy = policy(
+            root,
+            vec![rule(
+                &[ToolKind::Edit],
+                None,
+                &["src/**"],
+                PolicyDecision::Allow,
+            )],
+        );
+
+        let inside = tool_call(
+            ToolKind::Edit,
+            "Edit",
+            &[&root.join("src/a/b.rs"), Path::new("src/c.rs")],
+        );
+        let mixed = tool_call(
+            ToolKind::Edit,
+            "Edit",
+            &[&root.join("src/a.rs"), &root.join("Cargo.toml")],
+        );
+        let outside = tool_call(ToolKind::Edit, "Edit", &[Path::new("/etc/passwd")]);
+        let none = tool_call(ToolKind::Edit, "Edit", &[]);
+
+        assert_eq!(policy.evaluate(&inside), PolicyDecision::Allow);
+        assert_eq!(policy.evaluate(&mixed), PolicyDecision::Ask);
+        assert_eq!(policy.evaluate(&outside), PolicyDecision::Ask);
+        assert_eq!(policy.evaluate(&none), PolicyDecision::Ask);
+    }
+
+    #[test]
+    fn parent_dir_escapes_never_satisfy_allow_rules() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        let policy = policy(
+            root,
+            vec![rule(
+                &[ToolKind::Edit],
+                None,
+                &["src/**"],
+                PolicyDecision::Allow,
+            )],
+        );
+
+        for path in [
+            root.join("src/../../etc/passwd"),
+            PathBuf::from("src/../../etc/passwd"),
+            PathBuf::from("../outside/src/main.rs"),
+        ] {
+            let call = tool_call(ToolKind::Edit, "Edit", &[&path]);
+            assert_eq!(policy.evaluate(&call), PolicyDecision::Ask, "{path:?}");
+        }
+
+        let within = tool_call(
+            ToolKind::Edit,
+            "Edit",
+            &[
+                &root.join("docs/../src/./lib.rs"),
+                Path::new("./src/a/../b.rs"),
+            ],
+        );
+        assert_eq!(policy.evaluate(&within), PolicyDecision::Allow);
+    }
+
+    #[test]
+    fn deny_rules_match_any_path() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        let policy = policy(
+            root,
+            vec![
+                rule(&[], None, &[".env*", "**/.env*"], PolicyDecision::Deny),
+                rule(&[ToolKind::Edit], None, &[], PolicyDecision::Allow),
+            ],
+        );
+        let call = tool_call(
+            ToolKind::Edit,
+            "Edit",
+            &[&root.join("src/main.rs"), &root.join("app/.env.local")],
+        );
+        assert_eq!(policy.evaluate(&call), PolicyDecision::Deny);
+    }
+
+    #[test]
+    fn ask_rules_stop_later_rules_but_not_remembered_choices() {
+        let temp = TempDir::new().unwrap();
+        let mut policy = policy(
+            temp.path(),
+            vec![
+                rule(&[ToolKind::Execute], None, &[], PolicyDecision::Ask),
+                rule(&[], None, &[], PolicyDecision::Allow),
+            ],
+        );
+        let call = tool_call(ToolKind::Execute, "Run cargo test", &[]);
+        assert_eq!(policy.evaluate(&call), PolicyDecision::Ask);
+
+        policy.remember(&call, PolicyDecision::Allow).unwrap();
+        assert_eq!(policy.evaluate(&call), PolicyDecision::Allow);
+    }
+
+    #[test]
+    fn remembered_choices_persist_across_loads() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        let call = tool_call(ToolKind::Execute, "Run npm test [ci]", &[]);
+        let other = tool_call(ToolKind::Execute, "Run npm publish", &[]);
+
+        let mut policy = PermissionPolicy::load(root).unwrap();
+        assert!(policy.remember(&call, PolicyDecision::Allow).unwrap());
+        assert!(policy.remember(&call, PolicyDecision::Allow).unwrap());
+
+        let reloaded = PermissionPolicy::load(root).unwrap();
+        assert_eq!(reloaded.file.remembered.len(), 1);
+        assert_eq!(reloaded.evaluate(&call), PolicyDecision::Allow);
+        assert_eq!(reloaded.evaluate(&other), PolicyDecision::Ask);
+    }
+
+    #[test]
+    fn decisions_select_matching_options() {
+        let options = vec![
+            PermissionOption::new("always", "Always", PermissionOptionKind::AllowAlways),
+            PermissionOption::new("once", "Once", PermissionOptionKind::AllowOnce),
+            PermissionOption::new("no", "No", PermissionOptionKind::RejectAlways),
+        ];
+        let selected = |decision: PolicyDecision| match decision.select(&options) {
+            Some(RequestPermissionOutcome::Selected(selected)) => {
+                Some(selected.option_id.to_string())
+            }
+            _ => None,
+        };
+        assert_eq!(selected(PolicyDecision::Allow).as_deref(), Some("once"));
+        assert_eq!(selected(PolicyDecision::Deny).as_deref(), Some("no"));
+        assert_eq!(selected(PolicyDecision::Ask), None);
+
+        let allow_only = &options[..2];
+        assert!(matches!(
+            PolicyDecision::Deny.select(allow_only),
+            Some(RequestPermissionOutcome::Cancelled)
+        ));
+        let reject_only = &options[2..];
+        assert!(PolicyDecision::Allow.select(reject_only).is_none());
+    }
+}
@@ -0,0 +1,336 @@
+//! Path confinement for agent file access.
+//!
+//! Threat model: the agent process is untrusted input. Every path it sends in
+//! `fs/read_text_file`, `fs/write_text_file` and `terminal/create` is treated as hostile and
+//! must resolve to a location inside the project root or one of the configured extra roots,
+//! and outside the denylist. The agent may also run commands that reshape the tree between
+//! our check and our write (creating symlinks, swapping a directory for a link); we defend
+//! against that by resolving symlinks instead of trusting lexical paths, re-checking each
+//! directory we create and the parent directory right before a write lands, and only ever
+//! writing through in-root symlinks. What remains is the window between that last check
+//! and the rename itself; closing it needs `openat`-style descriptor-relative IO, which is
+//! out of scope here. The user and the files they open are trusted; so are the roots they
+//! configure.
+//!
+//! Not covered: hard links (a hard link inside the root aliases its target by design), and
+//! processes the agent starts in terminals, which run with the user's full privileges.
+
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use std::path::{Component, Path, PathBuf};
+
+/// Denied for agent reads and writes unless the user removes them: repository internals,
+/// Visor's own config and permission files, and dotenv secrets.
+pub const DEFAULT_DENIED_PATHS: &[&str] =
+    &["**/.git", "**/.git/**", "**/.acp", "**/.acp/**", "**/.env*"];
+
+pub struct Sandbox {
+    /// Canonical roots; the first is the project root.
+    roots: Vec<PathBuf>,
+    denied: GlobSet,
+}
+
+impl Sandbox {
+    /// `root` must already be canonical. Extra roots must exist and are taken relative to
+    /// `root` unless absolute; denied patterns are globs relative to whichever root a path
+    /// falls under, added to `DEFAULT_DENIED_PATHS`.
+    pub fn new(
+        root: PathBuf,
+        extra_roots: &[PathBuf],
+        denied_paths: &[String],
+    ) -> Result<Self, String> {
+        let mut roots = vec![root];
+        for extra in extra_roots {
+            let canonical = roots[0]
+                .join(extra)
+                .canonicalize()
+                .map_err(|err| format!("invalid extra root {}: {err}", extra.display()))?;
+            roots.push(canonical);
+        }
+
+        let mut denied = GlobSetBuilder::new();
+        let patterns = DEFAULT_DENIED_PATHS
+            .iter()
+            .copied()
+            .chain(denied_paths.iter().map(String::as_str));
+        for pattern in patterns {
+            denied.add(glob(pattern)?);
+        }
+        let denied = denied
+            .build()
+            .map_err(|err| format!("invalid denied paths: {err}"))?;
+
+        Ok(Self { roots, denied })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.roots[0]
+    }
+
+    /// Resolves an agent-supplied path to the canonical location it would touch. The path
+    /// need not exist: missing trailing components, including whole new directories, are
+    /// appended to the nearest existing ancestor after that ancestor is canonicalized.
+    pub fn resolve(&self, path: &Path) -> Result<PathBuf, String> {
+        if path.as_os_str().is_empty() {
+            return Err("path is empty".to_string());
+        }
+        if path.components().any(|c| matches!(c, Component::ParentDir)) {
+            return Err("parent paths are not allowed".to_string());
+        }
+        let candidate = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.root().join(path)
+        };
+
+        let mut missing = Vec::new();
+        let mut existing = candidate.as_path();
+        let canonical = loop {
+            match std::fs::symlink_metadata(existing) {
+                Ok(_) => {
+                    let canonical = existing.canonicalize().map_err(|err| {
+                        format!("failed to resolve {}: {err}", existing.display())
+                    })?;
+                    break canonical;
+                }
+                Err(_) => {
+                    let name = existing
+                        .file_name()
+                        .ok_or_else(|| "path has no existing ancestor".to_string())?;
+                    missing.push(name.to_owned());
+                    existing = existing
+                        .parent()
+                        .ok_or_else(|| "path has no existing ancestor".to_string())?;
+                }
+            }
+        };
+        let resolved = missing
+            .iter()
+            .rev()
+            .fold(canonical, |path, name| path.join(name));
+
+        self.check(&resolved)?;
+        Ok(resolved)
+    }
+
+    /// Confirms a directory about to receive a write still resolves inside the sandbox. Run
+    /// after creating directories and right before renaming a file into place, so a
+    /// symlink swapped in after `resolve` is caught.
+    pub fn recheck_dir(&self, dir: &Path) -> Result<(), String> {
+        let canonical = dir
+            .canonicalize()
+            .map_err(|err| format!("failed to resolve {}: {err}", dir.display()))?;
+        self.check(&canonical)
+    }
+
+    /// Creates `dir` and any missing parents without leaving the sandbox. The deepest
+    /// existing ancestor is rechecked before anything is created, and each new directory
+    /// right after it is made, so a symlink swapped in along the way stops the walk
+    /// instead of having directories created through it.
+    pub fn create_dirs(&self, dir: &Path) -> Result<(), String> {
+        let mut missing = Vec::new();
+        let mut existing = dir;
+        while std::fs::symlink_metadata(existing).is_err() {
+            missing.push(existing);
+            existing = existing
+                .parent()
+                .ok_or_else(|| "path has no existing ancestor".to_string())?;
+        }
+        self.recheck_dir(existing)?;
+        for dir in missing.into_iter().rev() {
+            match std::fs::create_dir(dir) {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(err) => {
+                    return Err(format!("failed to create {}: {err}", dir.display()));
+                }
+            }
+            self.recheck_dir(dir)?;
+        }
+        Ok(())
+    }
+
+    fn check(&self, resolved: &Path) -> Result<(), String> {
+        let root = self
+            .roots
+            .iter()
+            .find(|root| resolved.starts_with(root))
+            .ok_or_else(|| "path is outside project root".to_string())?;
+        let relative = resolved.strip_prefix(root).unwrap_or(resolved);
+        if !relative.as_os_str().is_empty() && self.denied.is_match(relative) {
+            return Err(format!("access to {} is denied", relative.display()));
+        }
+        Ok(())
+    }
+}
+
+fn glob(pattern: &str) -> Result<Glob, String> {
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map_err(|err| format!("invalid denied path {pattern}: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixture {
+        _dirs: (tempfile::TempDir, tempfile::TempDir),
+        root: PathBuf,
+        outside: PathBuf,
+    }
+
+    fn fixture() -> Fixture {
+        let root_dir = tempfile::tempdir().unwrap();
+        let outside_dir = tempfile::tempdir().unwrap();
+        let root = root_dir.path().canonicalize().unwrap();
+        let outside = outside_dir.path().canonicalize().unwrap();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(outside.join("secret"), "secret").unwrap();
+        Fixture {
+            _dirs: (root_dir, outside_dir),
+            root,
+            outside,
+        }
+    }
+
+    fn denied(sandbox: &Sandbox, path: &Path) -> String {
+        sandbox.resolve(path).unwrap_err()
+    }
+
+    #[test]
+    fn resolves_existing_files_and_new_nested_directories() {
+        let f = fixture();
+        let sandbox = Sandbox::new(f.root.clone(), &[], &[]).unwrap();
+
+        assert_eq!(
+            sandbox.resolve(&f.root.join("src/main.rs")).unwrap(),
+            f.root.join("src/main.rs")
+        );
+        assert_eq!(
+            sandbox.resolve(Path::new("src/main.rs")).unwrap(),
+            f.root.join("src/main.rs")
+        );
+        assert_eq!(
+            sandbox.resolve(&f.root.join("src/a/b/c.rs")).unwrap(),
+            f.root.join("src/a/b/c.rs")
+        );
+        assert_eq!(
+            sandbox.resolve(&f.root.join("./src/./lib.rs")).unwrap(),
+            f.root.join("src/lib.rs")
+        );
+    }
+
+    #[test]
+    fn rejects_lexical_escapes() {
+        let f = fixture();
+        let sandbox = Sandbox::new(f.root.clone(), &[], &[]).unwrap();
+
+        assert!(denied(&sandbox, &f.root.join("../x")).contains("parent"));
+        assert!(denied(&sandbox, &f.root.join("src/../../x")).contains("parent"));
+        assert!(denied(&sandbox, Path::new("../x")).contains("parent"));
+        assert!(denied(&sandbox, &f.outside.join("secret")).contains("outside"));
+        assert!(denied(&sandbox, &f.outside.join("new/file")).contains("outside"));
+        assert!(denied(&sandbox, Path::new("/")).contains("outside"));
+        assert!(denied(&sandbox, Path::new("")).contains("empty"));
+
+        let sibling = PathBuf::from(format!("{}-evil/file", f.root.display()));
+        assert!(denied(&sandbox, &sibling).contains("outside"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlink_escapes() {
+        use std::os::unix::fs::symlink;
+        let f = fixture();
+        let sandbox = Sandbox::new(f.root.clone(), &[], &[]).unwrap();
+
+        symlink(f.outside.join("secret"), f.root.join("file-link")).unwrap();
+        symlink(&f.outside, f.root.join("dir-link")).unwrap();
+        symlink(f.outside.join("missing"), f.root.join("dangling")).unwrap();
+        symlink(f.root.join("src"), f.root.join("inner-link")).unwrap();
+
+        assert!(denied(&sandbox, &f.root.join("file-link")).contains("outside"));
+        assert!(denied(&sandbox, &f.root.join("dir-link/secret")).contains("outside"));
+        assert!(denied(&sandbox, &f.root.join("dir-link/new/nested.rs")).contains("outside"));
+        assert!(denied(&sandbox, &f.root.join("dangling")).contains("failed to resolve"));
+        assert_eq!(
+            sandbox.resolve(&f.root.join("inner-link/main.rs")).unwrap(),
+            f.root.join("src/main.rs")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn recheck_catches_directories_swapped_for_symlinks() {
+        let f = fixture();
+        let sandbox = Sandbox::new(f.root.clone(), &[], &[]).unwrap();
+        let target = sandbox.resolve(&f.root.join("build/out.txt")).unwrap();
+        std::os::unix::fs::symlink(&f.outside, f.root.join("build")).unwrap();
+
+        let err = sandbox.recheck_dir(target.parent().unwrap()).unwrap_err();
+        assert!(err.contains("outside"), "{err}");
+
+        let err = sandbox
+            .create_dirs(&f.root.join("build/nested/deeper"))
+            .unwrap_err();
+        assert!(err.contains("outside"), "{err}");
+        let created: Vec<_> = std::fs::read_dir(&f.outside)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(created, ["secret"]);
+
+        sandbox
+            .create_dirs(&f.root.join("src/nested/deeper"))
+            .unwrap();
+        assert!(f.root.join("src/nested/deeper").is_dir());
+    }
+
+    #[test]
+    fn denylist_applies_under_every_root() {
+        let f = fixture();
+        let sandbox = Sandbox::new(
+            f.root.clone(),
+            std::slice::from_ref(&f.outside),
+            &["secrets/**".to_string()],
+        )
+        .unwrap();
+
+        for path in [
+            ".git",
+            ".git/config",
+            "vendor/lib/.git/HEAD",
+            ".acp/permissions.json",
+            ".env",
+            ".env.local",
+            "config/.env.production",
+            "secrets/key.pem",
+        ] {
+            let err = denied(&sandbox, &f.root.join(path));
+            assert!(err.contains("denied"), "{path}: {err}");
+        }
+        assert!(denied(&sandbox, &f.outside.join(".env")).contains("denied"));
+
+        assert!(sandbox.resolve(&f.root.join("src/env.rs")).is_ok());
+        assert!(sandbox.resolve(&f.root.join(".gitignore")).is_ok());
+        assert!(sandbox.resolve(&f.root.join("docs/.acpignore")).is_ok());
+        assert!(sandbox.resolve(&f.outside.join("secret")).is_ok());
+        assert!(sandbox.resolve(&f.root).is_ok());
+    }
+
+    #[test]
+    fn invalid_configuration_is_reported() {
+        let f = fixture();
+        assert!(Sandbox::new(f.root.clone(), &[f.root.join("missing")], &[]).is_err());
+        assert!(Sandbox::new(f.root.clone(), &[], &["[".to_string()]).is_err());
+    }
+
+    #[test]
+    fn relative_extra_roots_are_taken_from_the_project_root() {
+        let f = fixture();
+        let sandbox = Sandbox::new(f.root.clone(), &[PathBuf::from("src")], &[]).unwrap();
+        assert_eq!(sandbox.roots[1], f.root.join("src"));
+    }
+}
@@ -0,0 +1,446 @@
+use crate::acp::sandbox::Sandbox;
+use agent_client_protocol::Error;
+use serde_json::json;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+
+/// Largest response `fs/read_text_file` returns unless the agent config sets `max_read_bytes`.
+pub const DEFAULT_MAX_READ_BYTES: u64 = 10 * 1024 * 1024;
+
+/// How much of a file is inspected for NUL bytes before deciding it is binary.
+const BINARY_SNIFF_BYTES: usize = 8 * 1024;
+
+/// Why a text read failed. Each variant maps to an ACP error whose `data` carries a `reason`
+/// the agent can act on, e.g. retrying a large file with `line`/`limit`.
+#[derive(Debug)]
+pub enum ReadError {
+    Binary,
+    NotUtf8 {
+        line: u32,
+    },
+    /// `size` is the file size, or for a line window the bytes read when the limit was hit.
+    TooLarge {
+        size: u64,
+        max_bytes: u64,
+    },
+    Io(std::io::Error),
+}
+
+impl ReadError {
+    pub fn into_acp(self, path: &Path) -> Error {
+        let path = path.display().to_string();
+        match self {
+            ReadError::Binary => Error::invalid_params().data(json!({
+                "reason": "binary_file",
+                "path": path,
+                "message": "file appears to be binary",
+            })),
+            ReadError::NotUtf8 { line } => Error::invalid_params().data(json!({
+                "reason": "invalid_utf8",
+                "path": path,
+                "line": line,
+                "message": format!("line {line} is not valid UTF-8"),
+            })),
+            ReadError::TooLarge { size, max_bytes } => Error::invalid_params().data(json!({
+                "reason": "too_large",
+                "path": path,
+                "size": size,
+                "max_bytes": max_bytes,
+                "message": format!(
+                    "read of {size} bytes exceeds the {max_bytes} byte limit; request a smaller range with line/limit"
+                ),
+            })),
+            ReadError::Io(err) => {
+                Error::internal_error().data(format!("failed to read file: {err}"))
+            }
+        }
+    }
+}
+
+/// Reads `path` as text, or only `limit` lines starting at 1-based `line`. Lines are
+/// streamed so a window of a huge file never loads the rest of it, and are returned exactly
+/// as stored, line endings included.
+pub async fn read_text(
+    path: &Path,
+    line: Option<u32>,
+    limit: Option<u32>,
+    max_bytes: u64,
+) -> Result<String, ReadError> {
+    let mut file = tokio::fs::File::open(path).await.map_err(ReadError::Io)?;
+
+    let mut head = vec![0u8; BINARY_SNIFF_BYTES];
+    let mut filled = 0;
+    while filled < head.len() {
+        let read = file
+            .read(&mut head[filled..])
+            .await
+            .map_err(ReadError::Io)?;
+        if read == 0 {
+            break;
+        }
+        filled += read;
+    }
+    head.truncate(filled);
+    if head.contains(&0) {
+        return Err(ReadError::Binary);
+    }
+
+    if line.is_none() && limit.is_none() {
+        let size = file.metadata().await.map_err(ReadError::Io)?.len();
+        if size > max_bytes {
+            return Err(ReadError::TooLarge { size, max_bytes });
+        }
+    }
+
+    let start = line.unwrap_or(1).max(1);
+    let limit = limit.unwrap_or(u32::MAX);
+    let mut reader = BufReader::new(std::io::Cursor::new(head).chain(file));
+    let mut output = String::new();
+    let mut raw = Vec::new();
+    let mut current = 1u32;
+    let mut taken = 0u32;
+    while taken < limit {
+        // Consume one line chunk by chunk: lines before the window are never buffered, and an
+        // oversized line fails as soon as it crosses the limit.
+        let keep = current >= start;
+        let mut at_eof = true;
+        raw.clear();
+        loop {
+            let buf = reader.fill_buf().await.map_err(ReadError::Io)?;
+            if buf.is_empty() {
+                break;
+            }
+            at_eof = false;
+            let (len, done) = match buf.iter().position(|&byte| byte == b'\n') {
+                Some(newline) => (newline + 1, true),
+                None => (buf.len(), false),
+            };
+            if keep {
+                raw.extend_from_slice(&buf[..len]);
+                let size = (output.len() + raw.len()) as u64;
+                if size > max_bytes {
+                    return Err(ReadError::TooLarge { size, max_bytes });
+                }
+            }
+            reader.consume(len);
+            if done {
+                break;
+            }
+        }
+        if at_eof {
+            break;
+        }
+        if keep {
+            let text =
+                std::str::from_utf8(&raw).map_err(|_| ReadError::NotUtf8 { line: current })?;
+            output.push_str(text);
+            taken += 1;
+        }
+        current = current.saturating_add(1);
+    }
+    Ok(output)
+}
+
+/// Applies the same `line`/`limit` window and `max_bytes` cap to content that is already in
+/// memory, such as an unsaved buffer or a staged write.
+pub fn window_text(
+    content: &str,
+    line: Option<u32>,
+    limit: Option<u32>,
+    max_bytes: u64,
+) -> Result<String, ReadError> {
+    if line.is_none() && limit.is_none() {
+        let size = content.len() as u64;
+        if size > max_bytes {
+            return Err(ReadError::TooLarge { size, max_bytes });
+        }
+        return Ok(content.to_string());
+    }
+    let start = line.unwrap_or(1).max(1) as usize - 1;
+    let limit = limit.unwrap_or(u32::MAX) as usize;
+    let mut output = String::new();
+    for text in content.split_inclusive('\n').skip(start).take(limit) {
+        output.push_str(text);
+        let size = output.len() as u64;
+        if size > max_bytes {
+            return Err(ReadError::TooLarge { size, max_bytes });
+        }
+    }
+    Ok(output)
+}
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+/// The dominant line ending of existing file content, if it has any line breaks.
+fn line_ending(content: &[u8]) -> Option<LineEnding> {
+    let lf = content.iter().filter(|&&byte| byte == b'\n').count();
+    let crlf = content.windows(2).filter(|pair| pair == b"\r\n").count();
+    match lf {
+        0 => None,
+        _ if crlf > lf - crlf => Some(LineEnding::Crlf),
+        _ => Some(LineEnding::Lf),
+    }
+}
+
+/// Shapes new text like the file it replaces: a CRLF file stays CRLF and an LF file stays
+/// LF when the agent wrote uniformly in the other style, and a UTF-8 BOM is kept. Content
+/// with mixed endings is left alone since it is presumably deliberate.
+pub fn conform_to_previous(content: &str, previous: Option<&[u8]>) -> String {
+    let Some(previous) = previous else {
+        return content.to_string();
+    };
+    let crlf = content.matches("\r\n").count();
+    let lone_lf = content.matches('\n').count() - crlf;
+    let mut text = match line_ending(previous) {
+        Some(LineEnding::Crlf) if crlf == 0 => content.replace('\n', "\r\n"),
+        Some(LineEnding::Lf) if lone_lf == 0 => content.replace("\r\n", "\n"),
+        _ => content.to_string(),
+    };
+    if previous.starts_with(UTF8_BOM) && !text.starts_with('\u{feff}') {
+        text.insert(0, '\u{feff}');
+    }
+    text
+}
+
+/// Where a write to `path` actually lands. `path` was resolved by the sandbox earlier, but
+/// a symlink may have appeared there since; it is followed only if its target passes the
+/// sandbox too, and refused if it dangles.
+pub fn resolve_write_target(sandbox: &Sandbox, path: &Path) -> Result<PathBuf, String> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => sandbox.resolve(path).map_err(|err| {
+            format!(
+                "refusing to write through symlink {}: {err}",
+                path.display()
+            )
+        }),
+        _ => Ok(path.to_path_buf()),
+    }
+}
+
+/// Replaces `path` with `bytes` via a temp file in the same directory and a rename, so a
+/// crash leaves either the old file or the new one. An existing file's permissions carry
+/// over; a new file is created 0666 less the umask, like any file a plain write creates,
+/// instead of keeping the temp file's private mode.
+pub fn atomic_write(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let dir = path.parent().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "path has no parent")
+    })?;
+    let existing = std::fs::metadata(path)
+        .ok()
+        .map(|metadata| metadata.permissions());
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(".visor-write-");
+    #[cfg(unix)]
+    if existing.is_none() {
+        use std::os::unix::fs::PermissionsExt;
+        // open(2) masks the mode with the process umask.
+        builder.permissions(std::fs::Permissions::from_mode(0o666));
+    }
+    let mut temp = builder.tempfile_in(dir)?;
+    temp.write_all(bytes)?;
+    temp.as_file().sync_all()?;
+    if let Some(permissions) = existing {
+        temp.as_file().set_permissions(permissions)?;
+    }
+    temp.persist(path).map_err(|err| err.error)?;
+    Ok(())
+}
+
+/// Writes agent text over `path` the way `fs/write_text_file` should: following symlinks
+/// only into the sandbox, matching the previous file's line endings and BOM, and atomically.
+/// Returns the path written and the exact bytes that landed on disk.
+pub fn write_text(
+    sandbox: &Sandbox,
+    path: &Path,
+    content: &str,
+    previous: Option<&[u8]>,
+) -> Result<(PathBuf, Vec<u8>), String> {
+    let target = resolve_write_target(sandbox, path)?;
+    if let Some(parent) = target.parent() {
+        sandbox.recheck_dir(parent)?;
+    }
+    let bytes = conform_to_previous(content, previous).into_bytes();
+    atomic_write(&target, &bytes).map_err(|err| format!("failed to write file: {err}"))?;
+    Ok((target, bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(
+        content: &[u8],
+        line: Option<u32>,
+        limit: Option<u32>,
+    ) -> Result<String, ReadError> {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), content).unwrap();
+        read_text(file.path(), line, limit, DEFAULT_MAX_READ_BYTES).await
+    }
+
+    #[tokio::test]
+    async fn windows_keep_line_endings_and_trailing_newlines() {
+        let content = b"one\r\ntwo\nthree\r\nfour";
+        assert_eq!(
+            read(content, None, None).await.unwrap(),
+            "one\r\ntwo\nthree\r\nfour"
+        );
+        assert_eq!(
+            read(content, Some(2), Some(2)).await.unwrap(),
+            "two\nthree\r\n"
+        );
+        assert_eq!(read(content, Some(4), None).await.unwrap(), "four");
+        assert_eq!(read(content, Some(9), Some(1)).await.unwrap(), "");
+        assert_eq!(read(b"a\nb\n", None, Some(5)).await.unwrap(), "a\nb\n");
+        assert_eq!(
+            window_text("one\r\ntwo\nthree", Some(2), None, u64::MAX).unwrap(),
+            "two\nthree"
+        );
+    }
+
+    #[tokio::test]
+    async fn binary_and_invalid_utf8_files_are_reported() {
+        assert!(matches!(
+            read(b"PK\x03\x04\0\0", None, None).await,
+            Err(ReadError::Binary)
+        ));
+        assert!(matches!(
+            read(b"fine\nbad \xff\n", None, None).await,
+            Err(ReadError::NotUtf8 { line: 2 })
+        ));
+        assert_eq!(
+            read(b"fine\nbad \xff\n", Some(1), Some(1)).await.unwrap(),
+            "fine\n"
+        );
+
+        let err = read(b"\0", None, None).await.unwrap_err();
+        let acp = err.into_acp(Path::new("blob.bin"));
+        assert_eq!(acp.data.unwrap()["reason"], "binary_file");
+    }
+
+    #[tokio::test]
+    async fn max_size_applies_to_whole_files_and_windows() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let content = "0123456789\n".repeat(10);
+        std::fs::write(file.path(), &content).unwrap();
+
+        assert!(matches!(
+            read_text(file.path(), None, None, 50).await,
+            Err(ReadError::TooLarge {
+                size: 110,
+                max_bytes: 50
+            })
+        ));
+        assert_eq!(
+            read_text(file.path(), Some(3), Some(2), 50).await.unwrap(),
+            "0123456789\n0123456789\n"
+        );
+        assert!(matches!(
+            read_text(file.path(), Some(1), Some(5), 50).await,
+            Err(ReadError::TooLarge { .. })
+        ));
+
+        assert!(matches!(
+            window_text(&content, None, None, 50),
+            Err(ReadError::TooLarge {
+                size: 110,
+                max_bytes: 50
+            })
+        ));
+        assert_eq!(
+            window_text(&content, Some(3), Some(2), 50).unwrap(),
+            "0123456789\n0123456789\n"
+        );
+        assert!(matches!(
+            window_text(&content, Some(1), Some(5), 50),
+            Err(ReadError::TooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn writes_keep_line_endings_bom_and_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let path = root.join("script.sh");
+        let previous = b"\xEF\xBB\xBF#!/bin/sh\r\necho old\r\n";
+        std::fs::write(&path, previous).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let sandbox = Sandbox::new(root.clone(), &[], &[]).unwrap();
+        let (target, bytes) =
+            write_text(&sandbox, &path, "#!/bin/sh\necho new\n", Some(previous)).unwrap();
+        assert_eq!(target, path);
+        assert_eq!(bytes, b"\xEF\xBB\xBF#!/bin/sh\r\necho new\r\n");
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+
+        assert_eq!(conform_to_previous("a\r\nb\r\n", Some(b"x\ny\n")), "a\nb\n");
+        assert_eq!(conform_to_previous("a\r\nb\n", Some(b"x\ny\n")), "a\r\nb\n");
+        assert_eq!(conform_to_previous("a\nb\n", None), "a\nb\n");
+        let leftovers: Vec<_> = std::fs::read_dir(&root).unwrap().collect();
+        assert_eq!(leftovers.len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn new_files_get_the_umask_default_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        // A plain create gets 0666 less the umask, whatever umask the tests run under.
+        std::fs::write(root.join("plain.txt"), "").unwrap();
+        let expected = std::fs::metadata(root.join("plain.txt"))
+            .unwrap()
+            .permissions()
+            .mode();
+
+        let sandbox = Sandbox::new(root.clone(), &[], &[]).unwrap();
+        let (target, _) = write_text(&sandbox, &root.join("new.txt"), "new", None).unwrap();
+        let mode = std::fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, expected & 0o777);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_followed_only_inside_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let real = root.join("real.txt");
+        std::fs::write(&real, "inside").unwrap();
+        std::os::unix::fs::symlink(&real, root.join("inner-link")).unwrap();
+        std::os::unix::fs::symlink(outside.path().join("secret"), root.join("outer-link")).unwrap();
+
+        let sandbox = Sandbox::new(root.clone(), &[], &[]).unwrap();
+        let (target, _) = write_text(&sandbox, &root.join("inner-link"), "updated", None).unwrap();
+        assert_eq!(target, real);
+        assert_eq!(std::fs::read_to_string(&real).unwrap(), "updated");
+        assert!(std::fs::symlink_metadata(root.join("inner-link"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+
+        std::fs::write(outside.path().join("secret"), "keep").unwrap();
+        let err = write_text(&sandbox, &root.join("outer-link"), "owned", None).unwrap_err();
+        assert!(err.contains("outside project root"), "{err}");
+        assert_eq!(
+            std::fs::read_to_string(outside.path().join("secret")).unwrap(),
+            "keep"
+        );
+    }
+}
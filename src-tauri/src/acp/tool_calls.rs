@@ -0,0 +1,258 @@
+use crate::acp::events::{UiDiff, UiToolCall, UiTrackedToolCall};
+use agent_client_protocol::{
+    ToolCall, ToolCallContent, ToolCallId, ToolCallStatus, ToolCallUpdate,
+};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+struct TrackedToolCall {
+    call: ToolCall,
+    diffs: Vec<UiDiff>,
+    touched: Vec<PathBuf>,
+}
+
+impl TrackedToolCall {
+    fn new(call: ToolCall) -> Self {
+        let mut tracked = Self {
+            call,
+            diffs: Vec::new(),
+            touched: Vec::new(),
+        };
+        tracked.collect();
+        tracked
+    }
+
+    /// Folds the current content and locations into the accumulated diffs and touched paths.
+    /// ACP updates replace `content` and `locations` wholesale, so without this a later status
+    /// update would drop the diff or the file list the agent reported earlier.
+    fn collect(&mut self) {
+        for content in &self.call.content {
+            if let ToolCallContent::Diff(diff) = content {
+                let path = diff.path.display().to_string();
+                let entry = UiDiff {
+                    path,
+                    old_text: diff.old_text.clone(),
+                    new_text: diff.new_text.clone(),
+                };
+                match self
+                    .diffs
+                    .iter_mut()
+                    .find(|existing| existing.path == entry.path)
+                {
+                    Some(existing) => *existing = entry,
+                    None => self.diffs.push(entry),
+                }
+                touch(&mut self.touched, &diff.path);
+            }
+        }
+        for location in &self.call.locations {
+            touch(&mut self.touched, &location.path);
+        }
+    }
+
+    fn to_ui(&self, root_dir: &Path) -> UiTrackedToolCall {
+        UiTrackedToolCall {
+            tool_call: UiToolCall::from(&self.call),
+            diffs: self.diffs.clone(),
+            touched_paths: self
+                .touched
+                .iter()
+                .map(|path| display_relative(path, root_dir))
+                .collect(),
+        }
+    }
+}
+
+/// Per-session record of every tool call, with updates merged into the call they belong to.
+pub struct ToolCallStore {
+    root_dir: PathBuf,
+    calls: HashMap<ToolCallId, TrackedToolCall>,
+    order: Vec<ToolCallId>,
+}
+
+impl ToolCallStore {
+    pub fn new(root_dir: PathBuf) -> Self {
+        Self {
+            root_dir,
+            calls: HashMap::new(),
+            order: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, call: ToolCall) -> UiTrackedToolCall {
+        let id = call.tool_call_id.clone();
+        let tracked = match self.calls.remove(&id) {
+            Some(mut existing) => {
+                existing.call = call;
+                existing.collect();
+                existing
+            }
+            None => {
+                self.order.push(id.clone());
+                TrackedToolCall::new(call)
+            }
+        };
+        let ui = tracked.to_ui(&self.root_dir);
+        self.calls.insert(id, tracked);
+        ui
+    }
+
+    /// Merges an update into its tool call. Updates for ids we have not seen yet (e.g. the
+    /// tool call inside a permission request) start a new entry.
+    pub fn apply(&mut self, update: ToolCallUpdate) -> UiTrackedToolCall {
+        let id = update.tool_call_id.clone();
+        match self.calls.get_mut(&id) {
+            Some(tracked) => {
+                tracked.call.update(update.fields);
+                tracked.collect();
+                tracked.to_ui(&self.root_dir)
+            }
+            None => {
+                let mut call = ToolCall::new(id, update.fields.title.clone().unwrap_or_default());
+                call.update(update.fields);
+                self.record(call)
+            }
+        }
+    }
+
+    /// Best guess at the tool call responsible for a file write, since `fs/write_text_file`
+    /// does not say: the most recent unfinished call that reported the path, falling back to
+    /// the most recent call still in progress.
+    pub fn attribute_write(&self, path: &Path) -> Option<String> {
+        let unfinished = || {
+            self.order
+                .iter()
+                .rev()
+                .filter_map(|id| self.calls.get(id))
+                .filter(|tracked| {
+                    matches!(
+                        tracked.call.status,
+                        ToolCallStatus::Pending | ToolCallStatus::InProgress
+                    )
+                })
+        };
+        unfinished()
+            .find(|tracked| tracked.touched.iter().any(|touched| touched == path))
+            .or_else(|| {
+                unfinished().find(|tracked| tracked.call.status == ToolCallStatus::InProgress)
+            })
+            .map(|tracked| tracked.call.tool_call_id.to_string())
+    }
+
+    pub fn snapshot(&self) -> Vec<UiTrackedToolCall> {
+        self.order
+            .iter()
+            .filter_map(|id| self.calls.get(id))
+            .map(|tracked| tracked.to_ui(&self.root_dir))
+            .collect()
+    }
+}
+
+fn touch(touched: &mut Vec<PathBuf>, path: &Path) {
+    if !touched.iter().any(|existing| existing == path) {
+        touched.push(path.to_path_buf());
+    }
+}
+
+/// Renders paths under the project root the way `list_files` does, so the codemap can match them.
+pub fn display_relative(path: &Path, root_dir: &Path) -> String {
+    match path.strip_prefix(root_dir) {
+        Ok(relative) => relative.to_string_lossy().replace('\\', "/"),
+        Err(_) => path.display().to_string(),
+    }
+}
+
+/// Accepts paths from the UI either absolute or relative to the project root.
+pub fn resolve_in_root(path: &Path, root_dir: &Path) -> PathBuf {
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        root_dir.join(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agent_client_protocol::{Diff, ToolCallLocation, ToolCallUpdateFields};
+
+    #[test]
+    fn updates_merge_into_their_call_and_keep_diffs_and_locations() {
+        let root = PathBuf::from("/project");
+        let mut store = ToolCallStore::new(root.clone());
+
+        store.record(
+            ToolCall::new("call-1", "Edit main.rs")
+                .locations(vec![ToolCallLocation::new(root.join("src/main.rs"))]),
+        );
+        store.apply(ToolCallUpdate::new(
+            "call-1",
+            ToolCallUpdateFields::new()
+                .content(vec![ToolCallContent::from(
+                    Diff::new(root.join("src/main.rs"), "new").old_text("old".to_string()),
+                )])
+                .locations(vec![ToolCallLocation::new(root.join("src/lib.rs"))]),
+        ));
+        let merged = store.apply(ToolCallUpdate::new(
+            "call-1",
+            ToolCallUpdateFields::new()
+                .status(ToolCallStatus::Completed)
+                .content(vec![ToolCallContent::from("done".to_string())]),
+        ));
+
+        assert_eq!(merged.tool_call.title, "Edit main.rs");
+        assert_eq!(merged.tool_call.status, ToolCallStatus::Completed);
+        assert_eq!(merged.diffs.len(), 1);
+        assert_eq!(merged.diffs[0].old_text.as_deref(), Some("old"));
+        assert_eq!(merged.touched_paths, vec!["src/main.rs", "src/lib.rs"]);
+        assert_eq!(store.snapshot().len(), 1);
+    }
+
+    #[test]
+    fn updates_for_unknown_calls_start_new_entries_in_order() {
+        let mut store = ToolCallStore::new(PathBuf::from("/project"));
+        store.apply(ToolCallUpdate::new(
+            "call-2",
+            ToolCallUpdateFields::new().title("Run tests".to_string()),
+        ));
+        store.record(ToolCall::new("call-3", "Read file"));
+
+        let ids: Vec<_> = store
+            .snapshot()
+            .into_iter()
+            .map(|call| call.tool_call.tool_call_id)
+            .collect();
+        assert_eq!(ids, vec!["call-2", "call-3"]);
+    }
+
+    #[test]
+    fn writes_are_attributed_to_the_unfinished_call_that_touched_the_file() {
+        let root = PathBuf::from("/project");
+        let mut store = ToolCallStore::new(root.clone());
+        store.record(
+            ToolCall::new("call-1", "Edit lib.rs")
+                .status(ToolCallStatus::InProgress)
+                .locations(vec![ToolCallLocation::new(root.join("src/lib.rs"))]),
+        );
+        store.record(ToolCall::new("call-2", "Run tests").status(ToolCallStatus::InProgress));
+
+        assert_eq!(
+            store.attribute_write(&root.join("src/lib.rs")).as_deref(),
+            Some("call-1")
+        );
+        assert_eq!(
+            store.attribute_write(&root.join("src/main.rs")).as_deref(),
+            Some("call-2")
+        );
+
+        store.apply(ToolCallUpdate::new(
+            "call-2",
+            ToolCallUpdateFields::new().status(ToolCallStatus::Completed),
+        ));
+        store.apply(ToolCallUpdate::new(
+            "call-1",
+            ToolCallUpdateFields::new().status(ToolCallStatus::Completed),
+        ));
+        assert_eq!(store.attribute_write(&root.join("src/lib.rs")), None);
+    }
+}
@@ -0,0 +1,152 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::Emitter;
+
+/// Unsaved editor contents keyed by canonical path. Only dirty buffers are kept, so a file
+/// without an entry reads from disk. Agents see these through `fs/read_text_file`.
+#[derive(Default)]
+pub struct BufferRegistry {
+    buffers: Mutex<HashMap<PathBuf, String>>,
+}
+
+/// Emitted on `editor://buffer-updated` when an agent writes into a dirty buffer.
+#[derive(Clone, Serialize)]
+pub struct BufferUpdate {
+    pub path: String,
+    pub content: String,
+}
+
+impl BufferRegistry {
+    pub fn read(&self, path: &Path) -> Option<String> {
+        let buffers = self.buffers.lock().ok()?;
+        buffers.get(&buffer_key(path)).cloned()
+    }
+
+    /// Replaces the content of a dirty buffer. Returns `false`, leaving the registry
+    /// untouched, when the file has no unsaved edits.
+    pub fn write_if_dirty(&self, path: &Path, content: &str) -> bool {
+        let Ok(mut buffers) = self.buffers.lock() else {
+            return false;
+        };
+        match buffers.get_mut(&buffer_key(path)) {
+            Some(buffer) => {
+                *buffer = content.to_string();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn set(&self, path: &Path, content: String) -> Result<(), String> {
+        self.buffers
+            .lock()
+            .map_err(|_| "buffer mutex poisoned".to_string())?
+            .insert(buffer_key(path), content);
+        Ok(())
+    }
+
+    fn take(&self, path: &Path) -> Result<Option<String>, String> {
+        Ok(self
+            .buffers
+            .lock()
+            .map_err(|_| "buffer mutex poisoned".to_string())?
+            .remove(&buffer_key(path)))
+    }
+}
+
+/// Canonicalizes the way ACP path validation does, so editor paths and agent paths agree
+/// even for files that do not exist yet.
+fn buffer_key(path: &Path) -> PathBuf {
+    if let Ok(canonical) = path.canonicalize() {
+        return canonical;
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => parent
+            .canonicalize()
+            .map(|parent| parent.join(name))
+            .unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Tells the editor an agent changed a dirty buffer instead of the file on disk.
+pub fn notify_buffer_updated(app: &tauri::AppHandle, path: &Path, content: &str) {
+    let _ = app.emit(
+        "editor://buffer-updated",
+        BufferUpdate {
+            path: path.display().to_string(),
+            content: content.to_string(),
+        },
+    );
+}
+
+/// The editor's view of a file: its unsaved buffer if dirty, otherwise the disk content.
+#[tauri::command]
+pub fn open_buffer(
+    path: String,
+    state: tauri::State<'_, BufferRegistry>,
+) -> Result<String, String> {
+    let path = PathBuf::from(path);
+    match state.read(&path) {
+        Some(content) => Ok(content),
+        None => std::fs::read_to_string(&path).map_err(|e| format!("read failed: {e}")),
+    }
+}
+
+/// Records unsaved edits; the file is dirty until saved or discarded.
+#[tauri::command]
+pub fn update_buffer(
+    path: String,
+    content: String,
+    state: tauri::State<'_, BufferRegistry>,
+) -> Result<(), String> {
+    state.set(Path::new(&path), content)
+}
+
+#[tauri::command]
+pub fn save_buffer(path: String, state: tauri::State<'_, BufferRegistry>) -> Result<(), String> {
+    let path = PathBuf::from(path);
+    let Some(content) = state.take(&path)? else {
+        return Ok(());
+    };
+    if let Err(e) = std::fs::write(&path, &content) {
+        state.set(&path, content)?;
+        return Err(format!("write failed: {e}"));
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn discard_buffer(path: String, state: tauri::State<'_, BufferRegistry>) -> Result<(), String> {
+    state.take(Path::new(&path)).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_dirty_buffers_shadow_the_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.rs");
+        std::fs::write(&path, "on disk").unwrap();
+        let registry = BufferRegistry::default();
+
+        assert_eq!(registry.read(&path), None);
+        assert!(!registry.write_if_dirty(&path, "agent"));
+
+        registry
+            .set(&dir.path().join("./main.rs"), "unsaved".to_string())
+            .unwrap();
+        assert_eq!(registry.read(&path).as_deref(), Some("unsaved"));
+
+        assert!(registry.write_if_dirty(&path, "agent"));
+        assert_eq!(registry.read(&path).as_deref(), Some("agent"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "on disk");
+
+        assert_eq!(registry.take(&path).unwrap().as_deref(), Some("agent"));
+        assert_eq!(registry.read(&path), None);
+    }
+}
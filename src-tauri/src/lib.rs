@@ -6,10 +6,25 @@ const MENU_OPEN_FOLDER: &str = "open-folder";
 const MENU_QUIT: &str = "quit";
 
 mod acp;
+mod buffers;
+mod recording;
 mod terminal;
 
-use acp::{acp_list_agents, acp_reload_config, acp_send_prompt, acp_start_session, acp_stop_session};
-use terminal::{resize_terminal, spawn_terminal, write_to_terminal, AppState};
+use acp::{
+    acp_accept_staged, acp_cancel_prompt, acp_kill_terminal, acp_list_agents,
+    acp_list_checkpoints, acp_list_sessions, acp_reject_staged, acp_reload_config,
+    acp_resize_terminal, acp_respond_permission, acp_rollback_file, acp_rollback_session,
+    acp_rollback_turn, acp_send_prompt, acp_session_changes, acp_session_status,
+    acp_set_session_mode, acp_staged_changes, acp_start_recording, acp_start_session,
+    acp_stop_recording, acp_stop_session, acp_tool_calls, acp_write_terminal,
+};
+use buffers::{discard_buffer, open_buffer, save_buffer, update_buffer, BufferRegistry};
+use recording::{list_recordings, read_recording};
+use terminal::{
+    close_terminal, get_terminal_snapshot, paste_to_terminal, resize_terminal, restart_terminal,
+    spawn_terminal, start_terminal_recording, stop_terminal_recording, write_to_terminal,
+    AppState,
+};
 
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 #[tauri::command]
@@ -18,7 +33,13 @@ fn greet(name: &str) -> String {
 }
 
 #[tauri::command]
-fn list_files(path: &str) -> Result<Vec<String>, String> {
+fn list_files(path: &str, state: tauri::State<'_, AppState>) -> Result<Vec<String>, String> {
+    let files = project_files(path)?;
+    state.set_workspace_root(std::path::PathBuf::from(path));
+    Ok(files)
+}
+
+fn project_files(path: &str) -> Result<Vec<String>, String> {
     let base = std::path::PathBuf::from(path);
     if !base.is_dir() {
         return Err("Not a directory".into());
@@ -101,20 +122,57 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .manage(AppState::default())
         .manage(acp::init_state())
+        .manage(BufferRegistry::default())
         .invoke_handler(tauri::generate_handler![
             greet,
             list_files,
             spawn_terminal,
             write_to_terminal,
+            paste_to_terminal,
             resize_terminal,
+            close_terminal,
+            restart_terminal,
+            get_terminal_snapshot,
+            start_terminal_recording,
+            stop_terminal_recording,
+            list_recordings,
+            read_recording,
+            open_buffer,
+            update_buffer,
+            save_buffer,
+            discard_buffer,
             acp_list_agents,
             acp_reload_config,
+            acp_list_sessions,
             acp_start_session,
             acp_stop_session,
-            acp_send_prompt
+            acp_send_prompt,
+            acp_cancel_prompt,
+            acp_set_session_mode,
+            acp_session_status,
+            acp_session_changes,
+            acp_list_checkpoints,
+            acp_rollback_session,
+            acp_rollback_turn,
+            acp_rollback_file,
+            acp_staged_changes,
+            acp_accept_staged,
+            acp_reject_staged,
+            acp_tool_calls,
+            acp_write_terminal,
+            acp_resize_terminal,
+            acp_kill_terminal,
+            acp_start_recording,
+            acp_stop_recording,
+            acp_respond_permission
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                app.state::<AppState>().close_all();
+            }
+        });
 }
 
 #[cfg(test)]
@@ -142,7 +200,7 @@ mod tests {
         )
         .unwrap();
 
-        let files = list_files(root.to_str().unwrap()).unwrap();
+        let files = project_files(root.to_str().unwrap()).unwrap();
         assert!(files.contains(&"src/main.rs".to_string()));
         assert!(!files.iter().any(|f| f.contains("build/output.js")));
         assert!(!files.iter().any(|f| f.contains("src/ignore.me")));
@@ -0,0 +1,278 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Where recordings live, relative to the project root. `.acp` is denied to agents, so
+/// they cannot rewrite the record of what they ran.
+const RECORDINGS_DIR: &str = ".acp/recordings";
+
+/// Writes a terminal's output as an asciicast v2 file: a JSON header line, then one
+/// `[seconds, code, data]` line per event. Lines go straight to the file, so a recording
+/// cut short by a crash is still playable up to its last event.
+pub struct Recorder {
+    file: File,
+    path: PathBuf,
+    started: Instant,
+}
+
+impl Recorder {
+    /// Starts `<root>/.acp/recordings/<name>-<unix millis>.cast` for a terminal of
+    /// `cols`×`rows`.
+    pub fn create(
+        root: &Path,
+        name: &str,
+        title: &str,
+        cols: u16,
+        rows: u16,
+    ) -> Result<Self, String> {
+        let dir = root.join(RECORDINGS_DIR);
+        fs::create_dir_all(&dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let stem = format!("{name}-{}", now.as_millis());
+        let mut attempt = 0;
+        let (file, path) = loop {
+            let path = match attempt {
+                0 => dir.join(format!("{stem}.cast")),
+                n => dir.join(format!("{stem}-{n}.cast")),
+            };
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => break (file, path),
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => attempt += 1,
+                Err(err) => return Err(format!("create {}: {err}", path.display())),
+            }
+        };
+        let mut recorder = Self {
+            file,
+            path,
+            started: Instant::now(),
+        };
+        recorder.write_line(&json!({
+            "version": 2,
+            "width": cols,
+            "height": rows,
+            "timestamp": now.as_secs(),
+            "title": title,
+        }))?;
+        Ok(recorder)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn output(&mut self, data: &str) -> Result<(), String> {
+        self.event("o", data)
+    }
+
+    pub fn resize(&mut self, cols: u16, rows: u16) -> Result<(), String> {
+        self.event("r", &format!("{cols}x{rows}"))
+    }
+
+    fn event(&mut self, code: &str, data: &str) -> Result<(), String> {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        self.write_line(&json!([elapsed, code, data]))
+    }
+
+    fn write_line(&mut self, value: &Value) -> Result<(), String> {
+        let mut line = value.to_string();
+        line.push('\n');
+        self.file
+            .write_all(line.as_bytes())
+            .map_err(|e| format!("write {}: {e}", self.path.display()))
+    }
+}
+
+/// The recorder of a terminal that may or may not be recording. Shared between the
+/// terminal's reader thread and the commands that start and stop it.
+#[derive(Default)]
+pub struct RecordingSlot {
+    recorder: Mutex<Option<Recorder>>,
+}
+
+impl RecordingSlot {
+    pub fn start(&self, recorder: Recorder) -> Result<PathBuf, String> {
+        let mut slot = self
+            .recorder
+            .lock()
+            .map_err(|_| "recording mutex poisoned".to_string())?;
+        if let Some(current) = slot.as_ref() {
+            return Err(format!("already recording to {}", current.path().display()));
+        }
+        let path = recorder.path().to_path_buf();
+        *slot = Some(recorder);
+        Ok(path)
+    }
+
+    /// Ends the recording, if any, and returns its file.
+    pub fn stop(&self) -> Option<PathBuf> {
+        let recorder = self.recorder.lock().ok()?.take()?;
+        Some(recorder.path)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.lock().is_ok_and(|slot| slot.is_some())
+    }
+
+    /// Appends output. A recording that fails to write is dropped rather than retried on
+    /// every chunk.
+    pub fn output(&self, data: &str) {
+        self.with_recorder(|recorder| recorder.output(data));
+    }
+
+    pub fn resize(&self, cols: u16, rows: u16) {
+        self.with_recorder(|recorder| recorder.resize(cols, rows));
+    }
+
+    fn with_recorder(&self, f: impl FnOnce(&mut Recorder) -> Result<(), String>) {
+        let Ok(mut slot) = self.recorder.lock() else {
+            return;
+        };
+        if let Some(recorder) = slot.as_mut() {
+            if let Err(err) = f(recorder) {
+                eprintln!("stopping terminal recording: {err}");
+                *slot = None;
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RecordingInfo {
+    pub name: String,
+    pub title: Option<String>,
+    pub timestamp: Option<u64>,
+    pub size: u64,
+}
+
+/// A recording read back for replay: the asciicast header and its events in order.
+#[derive(Serialize)]
+pub struct Recording {
+    pub header: Value,
+    pub events: Vec<(f64, String, String)>,
+}
+
+/// Recordings under `root`, newest first.
+pub fn list(root: &Path) -> Result<Vec<RecordingInfo>, String> {
+    let dir = root.join(RECORDINGS_DIR);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(format!("read {}: {err}", dir.display())),
+    };
+    let mut recordings = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("cast") {
+            continue;
+        }
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let header = read_header(&path).unwrap_or(Value::Null);
+        recordings.push(RecordingInfo {
+            name: name.to_string(),
+            title: header["title"].as_str().map(str::to_string),
+            timestamp: header["timestamp"].as_u64(),
+            size: entry.metadata().map(|meta| meta.len()).unwrap_or(0),
+        });
+    }
+    recordings.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then(b.name.cmp(&a.name)));
+    Ok(recordings)
+}
+
+/// Reads recording `name` under `root`. Lines that do not parse, such as a last line cut
+/// off mid-write, are skipped.
+pub fn read(root: &Path, name: &str) -> Result<Recording, String> {
+    let path = recording_path(root, name)?;
+    let file = File::open(&path).map_err(|e| format!("open {}: {e}", path.display()))?;
+    let mut lines = BufReader::new(file).lines();
+    let header = match lines.next() {
+        Some(Ok(line)) => serde_json::from_str(&line)
+            .map_err(|e| format!("{name} is not an asciicast file: {e}"))?,
+        _ => return Err(format!("{name} is empty")),
+    };
+    let events = lines
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str(&line).ok())
+        .collect();
+    Ok(Recording { header, events })
+}
+
+fn read_header(path: &Path) -> Option<Value> {
+    let file = File::open(path).ok()?;
+    let line = BufReader::new(file).lines().next()?.ok()?;
+    serde_json::from_str(&line).ok()
+}
+
+/// Only bare `.cast` file names are accepted, so a name cannot reach outside the
+/// recordings directory.
+fn recording_path(root: &Path, name: &str) -> Result<PathBuf, String> {
+    let bare = Path::new(name).file_name().and_then(|file| file.to_str()) == Some(name);
+    if !bare || !name.ends_with(".cast") {
+        return Err(format!("invalid recording name: {name}"));
+    }
+    Ok(root.join(RECORDINGS_DIR).join(name))
+}
+
+#[tauri::command]
+pub fn list_recordings(root: String) -> Result<Vec<RecordingInfo>, String> {
+    list(Path::new(&root))
+}
+
+#[tauri::command]
+pub fn read_recording(root: String, name: String) -> Result<Recording, String> {
+    read(Path::new(&root), &name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recordings_round_trip_through_list_and_read() {
+        let root = tempfile::tempdir().unwrap();
+        let slot = RecordingSlot::default();
+        slot.output("before");
+        let recorder = Recorder::create(root.path(), "terminal-1", "sh", 80, 24).unwrap();
+        let path = slot.start(recorder).unwrap();
+        assert!(slot
+            .start(Recorder::create(root.path(), "terminal-1", "sh", 80, 24).unwrap())
+            .is_err());
+        slot.output("$ echo \"hi\"\r\nhi\r\n");
+        slot.resize(100, 30);
+        assert_eq!(slot.stop(), Some(path.clone()));
+        slot.output("after");
+
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let listed = list(root.path()).unwrap();
+        assert!(listed.iter().any(|info| info.name == name));
+        assert!(listed
+            .iter()
+            .all(|info| info.title.as_deref() == Some("sh")));
+
+        let recording = read(root.path(), name).unwrap();
+        assert_eq!(recording.header["version"], 2);
+        assert_eq!(
+            (
+                recording.header["width"].clone(),
+                recording.header["height"].clone()
+            ),
+            (json!(80), json!(24))
+        );
+        let events: Vec<_> = recording
+            .events
+            .iter()
+            .map(|(_, code, data)| (code.as_str(), data.as_str()))
+            .collect();
+        assert_eq!(events, [("o", "$ echo \"hi\"\r\nhi\r\n"), ("r", "100x30")]);
+
+        assert!(read(root.path(), "../agents.json").is_err());
+        assert!(read(root.path(), "missing.cast").is_err());
+    }
+}
@@ -1,159 +1,1216 @@
-use portable_pty::{native_pty_system, CommandBuilder, MasterPty, PtyPair, PtySize};
+use crate::recording::{Recorder, RecordingSlot};
+use portable_pty::{
+    native_pty_system, ChildKiller, CommandBuilder, ExitStatus, MasterPty, PtyPair, PtySize,
+};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
 use std::io::{Read, Write};
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
 use std::thread;
-use tauri::Emitter;
+use std::time::{Duration, Instant};
+use tauri::{Emitter, Manager};
+
+/// How long the first chunk of a burst waits for more before it is emitted.
+const COALESCE_WINDOW: Duration = Duration::from_millis(8);
+/// A burst is emitted early once it reaches this many bytes.
+const COALESCE_LIMIT: usize = 64 * 1024;
+
+/// Bytes of output each user terminal keeps for `get_terminal_snapshot`.
+const SCROLLBACK_LIMIT: usize = 1024 * 1024;
+
+/// How long the watcher waits, after the shell exits, for the reader to emit the last of
+/// its output before announcing the exit.
+const DRAIN_GRACE: Duration = Duration::from_millis(200);
+
+/// How long a terminal whose shell exited stays registered, so a view can read its final
+/// snapshot or restart it, before it is reaped.
+const EXITED_TTL: Duration = Duration::from_secs(60);
 
 pub struct PtySession {
+    options: TerminalOptions,
     master: Arc<Mutex<Box<dyn MasterPty + Send>>>,
-    writer: Arc<Mutex<Box<dyn Write + Send>>>,
-    child: Option<Box<dyn portable_pty::Child + Send>>,
+    input: Arc<Input>,
+    killer: Box<dyn ChildKiller + Send + Sync>,
+    pid: Option<u32>,
+    scrollback: Arc<Mutex<Scrollback>>,
+    recording: Arc<RecordingSlot>,
+    /// When the shell exited, once it has.
+    exited: Arc<OnceLock<Instant>>,
     reader_handle: Option<thread::JoinHandle<()>>,
+    watcher_handle: Option<thread::JoinHandle<()>>,
 }
 
+impl PtySession {
+    fn snapshot(&self, id: u64) -> Result<TerminalSnapshot, String> {
+        let size = self
+            .master
+            .lock()
+            .map_err(|_| "master mutex poisoned".to_string())?
+            .get_size()
+            .map_err(|e| format!("size failed: {e}"))?;
+        let scrollback = self
+            .scrollback
+            .lock()
+            .map_err(|_| "scrollback mutex poisoned".to_string())?;
+        Ok(TerminalSnapshot {
+            id,
+            seq: scrollback.seq,
+            data: scrollback.output.text(),
+            truncated: scrollback.output.truncated(),
+            cols: size.cols,
+            rows: size.rows,
+            recording: self.recording.is_recording(),
+        })
+    }
+
+    /// The shell's input. A terminal whose shell has exited stays registered for its
+    /// scrollback until it is reaped, but takes no more input.
+    fn input(&self, id: u64) -> Result<&Arc<Input>, String> {
+        if self.exited.get().is_some() {
+            return Err(format!("terminal {id} has exited"));
+        }
+        Ok(&self.input)
+    }
+
+    /// Kills the shell and everything it started, then waits for the watcher to reap it
+    /// and the reader to drain the rest of its output.
+    fn close(mut self) {
+        let reader_running = self
+            .reader_handle
+            .as_ref()
+            .is_some_and(|handle| !handle.is_finished());
+        #[cfg(unix)]
+        if self.exited.get().is_none() || reader_running {
+            if let Some(pid) = self.pid {
+                // SAFETY: killpg only sends a signal. The group outlives the shell while a
+                // job still holds the PTY, and an empty group yields ESRCH.
+                unsafe {
+                    libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+                }
+            }
+        }
+        if self.exited.get().is_none() {
+            let _ = self.killer.kill();
+        }
+        if let Some(handle) = self.watcher_handle.take() {
+            let _ = handle.join();
+        }
+        drop(self.input);
+        drop(self.master);
+        if let Some(handle) = self.reader_handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// The tail of a terminal's output, how many `term-data` events it covers, and the paste
+/// mode the output last switched to.
+struct Scrollback {
+    output: OutputBuffer,
+    seq: u64,
+    paste_mode: PasteMode,
+}
+
+/// Sent by a program that wants pastes wrapped in `PASTE_START`/`PASTE_END`, and by the
+/// same program to turn that off again. Shells such as bash, zsh and fish switch it on
+/// around each prompt.
+const BRACKETED_PASTE_ON: &str = "\x1b[?2004h";
+const BRACKETED_PASTE_OFF: &str = "\x1b[?2004l";
+const PASTE_START: &str = "\x1b[200~";
+const PASTE_END: &str = "\x1b[201~";
+
+/// Follows bracketed paste mode through a terminal's output. The last few bytes of each
+/// chunk are kept so a mode switch split across chunks is still seen.
 #[derive(Default)]
-pub struct AppState {
-    pub pty: Mutex<Option<PtySession>>,
+struct PasteMode {
+    bracketed: bool,
+    tail: String,
 }
 
-fn default_shell() -> String {
-    if cfg!(windows) {
-        std::env::var("COMSPEC").unwrap_or_else(|_| "powershell.exe".to_string())
+impl PasteMode {
+    fn observe(&mut self, text: &str) {
+        let haystack = std::mem::take(&mut self.tail) + text;
+        let on = haystack.rfind(BRACKETED_PASTE_ON);
+        let off = haystack.rfind(BRACKETED_PASTE_OFF);
+        if on.is_some() || off.is_some() {
+            // `None` sorts before every position, so whichever switch came last wins.
+            self.bracketed = on > off;
+        }
+        let mut start = haystack.len().saturating_sub(BRACKETED_PASTE_ON.len() - 1);
+        while !haystack.is_char_boundary(start) {
+            start += 1;
+        }
+        self.tail = haystack[start..].to_string();
+    }
+}
+
+/// What a paste of `text` sends to the PTY: line breaks become carriage returns, as typed
+/// Enter would, and with bracketed paste on the text is wrapped so the shell inserts it
+/// rather than running it line by line. An end marker inside `text` is dropped so the
+/// paste cannot close its own brackets early.
+fn paste_payload(text: &str, bracketed: bool) -> String {
+    let text = text.replace("\r\n", "\r").replace('\n', "\r");
+    if bracketed {
+        format!("{PASTE_START}{}{PASTE_END}", text.replace(PASTE_END, ""))
     } else {
-        std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string())
+        text
     }
 }
 
-fn spawn_pty(app: &tauri::AppHandle, state: &AppState) -> Result<u64, String> {
-    // If a session already exists, do nothing.
-    if let Ok(guard) = state.pty.lock() {
-        if guard.is_some() {
-            return Ok(0);
+/// Bytes of a paste written per lock of the terminal's writer.
+const PASTE_CHUNK: usize = 4096;
+
+/// `^C`, `^Z` and `^\`: keys that stop a paste rather than wait behind it.
+const INTERRUPT_KEYS: [u8; 3] = [0x03, 0x1a, 0x1c];
+
+/// A terminal's input side. A paste holds `paste` for its whole payload, so two pastes
+/// never interleave, but takes `writer` one `PASTE_CHUNK` at a time. The PTY blocks writes
+/// once the program falls behind reading them, so rather than wait out a large paste,
+/// keystrokes that arrive during one are held in `pending`. Locks are taken in field order.
+struct Input {
+    paste: Mutex<()>,
+    /// `Some` while a paste is being written.
+    pending: Mutex<Option<Held>>,
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+/// Input typed while a paste is being written.
+#[derive(Default)]
+struct Held {
+    /// Sent right after the paste's end marker.
+    typed: Vec<u8>,
+    /// Keys containing an interrupt. These end the paste at the next chunk and go out right
+    /// after its end marker; like a tty flushing its input queue on ^C, they discard what was
+    /// typed before them.
+    interrupt: Option<Vec<u8>>,
+}
+
+impl Input {
+    fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            paste: Mutex::new(()),
+            pending: Mutex::new(None),
+            writer: Mutex::new(writer),
         }
     }
 
-    // Clean up any existing session to avoid duplicate outputs.
-    if let Ok(mut guard) = state.pty.lock() {
-        if let Some(mut session) = guard.take() {
-            if let Some(mut child) = session.child.take() {
-                let _ = child.kill();
-                let _ = child.wait();
+    /// Sends typed input, or holds it for the paste in progress. Never waits on a paste.
+    fn keys(&self, data: &[u8]) -> Result<(), String> {
+        let mut pending = self.pending()?;
+        let Some(held) = pending.as_mut() else {
+            return self.write(data);
+        };
+        if data.iter().any(|byte| INTERRUPT_KEYS.contains(byte)) {
+            held.typed.clear();
+            held.interrupt
+                .get_or_insert_with(Vec::new)
+                .extend_from_slice(data);
+        } else {
+            held.typed.extend_from_slice(data);
+        }
+        Ok(())
+    }
+
+    /// Writes `payload` in chunks, stopping early if an interrupt is typed, then closes the
+    /// bracket if it opened one and sends whatever was typed meanwhile.
+    fn paste(&self, payload: &[u8]) -> Result<(), String> {
+        let _paste = self
+            .paste
+            .lock()
+            .map_err(|_| "paste mutex poisoned".to_string())?;
+        *self.pending()? = Some(Held::default());
+        let (body, end) = match payload.strip_suffix(PASTE_END.as_bytes()) {
+            Some(body) => (body, PASTE_END.as_bytes()),
+            None => (payload, &[][..]),
+        };
+        let mut pasted = Ok(());
+        for chunk in body.chunks(PASTE_CHUNK) {
+            if self.interrupted()? {
+                break;
             }
-            if let Some(handle) = session.reader_handle.take() {
-                let _ = handle.join();
+            pasted = self.write(chunk);
+            if pasted.is_err() {
+                break;
             }
         }
+        let mut pending = self.pending()?;
+        let held = pending.take().unwrap_or_default();
+        pasted?;
+        let mut rest = end.to_vec();
+        rest.extend(held.interrupt.unwrap_or_default());
+        rest.extend(held.typed);
+        if rest.is_empty() {
+            return Ok(());
+        }
+        self.write(&rest)
     }
 
-    let pty_system = native_pty_system();
-    let pair: PtyPair = pty_system
-        .openpty(PtySize {
-            rows: 24,
-            cols: 80,
-            pixel_width: 0,
-            pixel_height: 0,
-        })
-        .map_err(|e| format!("openpty failed: {e}"))?;
+    fn interrupted(&self) -> Result<bool, String> {
+        Ok(self
+            .pending()?
+            .as_ref()
+            .is_some_and(|held| held.interrupt.is_some()))
+    }
+
+    fn pending(&self) -> Result<MutexGuard<'_, Option<Held>>, String> {
+        self.pending
+            .lock()
+            .map_err(|_| "input mutex poisoned".to_string())
+    }
+
+    fn write(&self, data: &[u8]) -> Result<(), String> {
+        let mut writer = self
+            .writer
+            .lock()
+            .map_err(|_| "writer mutex poisoned".to_string())?;
+        writer
+            .write_all(data)
+            .map_err(|e| format!("write failed: {e}"))?;
+        writer.flush().ok();
+        Ok(())
+    }
+}
 
-    let shell = default_shell();
-    let cmd = CommandBuilder::new(shell);
+/// The user's own terminals, keyed by the id `spawn_terminal` hands out.
+#[derive(Default)]
+pub struct AppState {
+    sessions: Mutex<HashMap<u64, PtySession>>,
+    next_id: AtomicU64,
+    workspace_root: Mutex<Option<PathBuf>>,
+}
+
+impl AppState {
+    /// Remembers the folder opened in the UI; new terminals start there by default.
+    pub fn set_workspace_root(&self, root: PathBuf) {
+        if let Ok(mut guard) = self.workspace_root.lock() {
+            *guard = Some(root);
+        }
+    }
+
+    /// Closes every terminal. Called on app exit so no shell outlives the window.
+    pub fn close_all(&self) {
+        let sessions: Vec<PtySession> = match self.sessions.lock() {
+            Ok(mut guard) => guard.drain().map(|(_, session)| session).collect(),
+            Err(_) => return,
+        };
+        for session in sessions {
+            session.close();
+        }
+    }
+
+    fn with_session<T>(
+        &self,
+        id: u64,
+        f: impl FnOnce(&PtySession) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let guard = self
+            .sessions
+            .lock()
+            .map_err(|_| "pty mutex poisoned".to_string())?;
+        let session = guard
+            .get(&id)
+            .ok_or_else(|| format!("terminal {id} not found"))?;
+        f(session)
+    }
+
+    fn take_session(&self, id: u64) -> Result<PtySession, String> {
+        self.sessions
+            .lock()
+            .map_err(|_| "pty mutex poisoned".to_string())?
+            .remove(&id)
+            .ok_or_else(|| format!("terminal {id} not found"))
+    }
+
+    fn insert(&self, id: u64, session: PtySession) -> Result<(), String> {
+        self.sessions
+            .lock()
+            .map_err(|_| "pty mutex poisoned".to_string())?
+            .insert(id, session);
+        Ok(())
+    }
+
+    /// Closes the terminals whose shells exited at least `ttl` ago. Sessions are picked by
+    /// exit time rather than id, so a terminal restarted under the same id is left alone.
+    fn reap_exited(&self, ttl: Duration) {
+        let reaped: Vec<PtySession> = match self.sessions.lock() {
+            Ok(mut guard) => {
+                let expired: Vec<u64> = guard
+                    .iter()
+                    .filter(|(_, session)| {
+                        session
+                            .exited
+                            .get()
+                            .is_some_and(|exited| exited.elapsed() >= ttl)
+                    })
+                    .map(|(id, _)| *id)
+                    .collect();
+                expired.iter().filter_map(|id| guard.remove(id)).collect()
+            }
+            Err(_) => return,
+        };
+        for session in reaped {
+            session.close();
+        }
+    }
+
+    /// Closes terminal `id`, running or exited, and registers what `start` makes of the
+    /// same options and recording under the same id.
+    fn restart(
+        &self,
+        id: u64,
+        start: impl FnOnce(TerminalOptions, Arc<RecordingSlot>) -> Result<PtySession, String>,
+    ) -> Result<(), String> {
+        let session = self.take_session(id)?;
+        let options = session.options.clone();
+        let recording = session.recording.clone();
+        session.close();
+        self.insert(id, start(options, recording)?)
+    }
+}
+
+/// Payload of the `term-data` event; `id` says which terminal produced `data`. `seq`
+/// counts the terminal's events from 1, so the UI can skip those a snapshot already has.
+#[derive(Clone, Serialize)]
+struct TerminalData {
+    id: u64,
+    seq: u64,
+    data: String,
+}
+
+/// What a remounted terminal view needs to pick up where the old one left off: the
+/// scrollback up to event `seq`, the size to fit it to, and whether it is being recorded.
+#[derive(Serialize)]
+pub struct TerminalSnapshot {
+    id: u64,
+    seq: u64,
+    data: String,
+    truncated: bool,
+    cols: u16,
+    rows: u16,
+    recording: bool,
+}
+
+/// Payload of the `term-exit` event, sent once per shell after its last output.
+#[derive(Clone, Serialize)]
+struct TerminalExit {
+    id: u64,
+    exit_code: u32,
+    success: bool,
+}
+
+/// A command running on the slave side of a fresh PTY. The writer is left on `master`:
+/// portable-pty sends EOF when a taken writer is dropped, so only take it to keep it.
+pub struct SpawnedPty {
+    pub master: Box<dyn MasterPty + Send>,
+    pub reader: Box<dyn Read + Send>,
+    pub child: Box<dyn portable_pty::Child + Send + Sync>,
+}
+
+/// Opens a PTY of `size` and spawns `cmd` in it. Shared by the user shell and the
+/// terminals agents create over ACP.
+pub fn open_pty(cmd: CommandBuilder, size: PtySize) -> Result<SpawnedPty, String> {
+    let pair: PtyPair = native_pty_system()
+        .openpty(size)
+        .map_err(|e| format!("openpty failed: {e}"))?;
     let child = pair
         .slave
         .spawn_command(cmd)
         .map_err(|e| format!("spawn command failed: {e}"))?;
-
-    let master = pair.master;
-    let mut reader = master
+    let reader = pair
+        .master
         .try_clone_reader()
         .map_err(|e| format!("reader failed: {e}"))?;
+    Ok(SpawnedPty {
+        master: pair.master,
+        reader,
+        child,
+    })
+}
+
+/// Decodes a byte stream read in arbitrary chunks. A multi-byte character split across
+/// reads is held back until its remaining bytes arrive; invalid bytes become U+FFFD.
+#[derive(Default)]
+pub struct Utf8Decoder {
+    pending: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    pub fn decode(&mut self, bytes: &[u8]) -> String {
+        let mut input = std::mem::take(&mut self.pending);
+        input.extend_from_slice(bytes);
+        let mut text = String::with_capacity(input.len());
+        let mut rest = input.as_slice();
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    break;
+                }
+                Err(err) => {
+                    let (valid, invalid) = rest.split_at(err.valid_up_to());
+                    text.push_str(&String::from_utf8_lossy(valid));
+                    match err.error_len() {
+                        Some(len) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            rest = &invalid[len..];
+                        }
+                        None => {
+                            self.pending = invalid.to_vec();
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        text
+    }
+
+    /// Flushes a character left incomplete at the end of the stream.
+    pub fn finish(&mut self) -> String {
+        if std::mem::take(&mut self.pending).is_empty() {
+            String::new()
+        } else {
+            char::REPLACEMENT_CHARACTER.to_string()
+        }
+    }
+}
+
+/// Terminal output capped at `limit` bytes. Old output is dropped from the
+/// front, never splitting a character, and nothing is reallocated while appending.
+pub struct OutputBuffer {
+    bytes: VecDeque<u8>,
+    limit: Option<usize>,
+    truncated: bool,
+}
+
+impl OutputBuffer {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            bytes: VecDeque::new(),
+            limit,
+            truncated: false,
+        }
+    }
+
+    pub fn push(&mut self, text: &str) {
+        let Some(limit) = self.limit else {
+            self.bytes.extend(text.as_bytes());
+            return;
+        };
+        let mut text = text;
+        if text.len() > limit {
+            let mut start = text.len() - limit;
+            while !text.is_char_boundary(start) {
+                start += 1;
+            }
+            text = &text[start..];
+            self.bytes.clear();
+            self.truncated = true;
+        }
+        let overflow = (self.bytes.len() + text.len()).saturating_sub(limit);
+        if overflow > 0 {
+            self.bytes.drain(..overflow);
+            while self.bytes.front().is_some_and(|byte| byte & 0xC0 == 0x80) {
+                self.bytes.pop_front();
+            }
+            self.truncated = true;
+        }
+        self.bytes.extend(text.as_bytes());
+    }
+
+    /// Whether anything has been dropped from the front.
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    pub fn text(&self) -> String {
+        let (front, back) = self.bytes.as_slices();
+        String::from_utf8_lossy(&[front, back].concat()).into_owned()
+    }
+}
+
+fn default_shell() -> String {
+    if cfg!(windows) {
+        std::env::var("COMSPEC").unwrap_or_else(|_| "powershell.exe".to_string())
+    } else {
+        std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string())
+    }
+}
+
+/// What to run in a new terminal. Anything left out falls back to the user's shell in
+/// the opened folder.
+#[derive(Clone, Default)]
+pub struct TerminalOptions {
+    pub cwd: Option<String>,
+    pub shell: Option<String>,
+    pub args: Vec<String>,
+    pub env: HashMap<String, String>,
+}
+
+/// Starts a shell for `options`. `on_output` runs on the reader thread for each chunk,
+/// after it is added to the scrollback, with the chunk's sequence number;
+/// `on_exit` runs once on the watcher thread after the shell is reaped and its output
+/// drained (or `DRAIN_GRACE` passed). Output is also written to `recording` while it
+/// holds a recorder.
+fn start_session(
+    options: TerminalOptions,
+    recording: Arc<RecordingSlot>,
+    on_output: impl Fn(&str, u64) + Send + 'static,
+    on_exit: impl FnOnce(&ExitStatus) + Send + 'static,
+) -> Result<PtySession, String> {
+    let mut cmd = CommandBuilder::new(options.shell.clone().unwrap_or_else(default_shell));
+    cmd.args(&options.args);
+    for (key, value) in &options.env {
+        cmd.env(key, value);
+    }
+    if let Some(cwd) = &options.cwd {
+        if !Path::new(cwd).is_dir() {
+            return Err(format!("{cwd} is not a directory"));
+        }
+        cmd.cwd(cwd);
+    }
+
+    let SpawnedPty {
+        master,
+        mut reader,
+        mut child,
+    } = open_pty(
+        cmd,
+        PtySize {
+            rows: 24,
+            cols: 80,
+            pixel_width: 0,
+            pixel_height: 0,
+        },
+    )?;
     let writer = master
         .take_writer()
         .map_err(|e| format!("writer failed: {e}"))?;
     let master = Arc::new(Mutex::new(master));
+    let killer = child.clone_killer();
+    let pid = child.process_id();
 
-    let app_handle = app.clone();
+    let scrollback = Arc::new(Mutex::new(Scrollback {
+        output: OutputBuffer::new(Some(SCROLLBACK_LIMIT)),
+        seq: 0,
+        paste_mode: PasteMode::default(),
+    }));
+    let reader_scrollback = scrollback.clone();
+    let reader_recording = recording.clone();
+    let (drained_tx, drained_rx) = mpsc::channel::<()>();
     let reader_handle = thread::spawn(move || {
-        let mut buf = [0u8; 1024];
+        let _drained = drained_tx;
+        let (chunk_tx, chunk_rx) = mpsc::channel();
+        let emitter = thread::spawn(move || {
+            coalesce_output(chunk_rx, |text| {
+                let Ok(mut scrollback) = reader_scrollback.lock() else {
+                    return;
+                };
+                scrollback.output.push(text);
+                scrollback.paste_mode.observe(text);
+                scrollback.seq += 1;
+                let seq = scrollback.seq;
+                drop(scrollback);
+                reader_recording.output(text);
+                on_output(text, seq);
+            })
+        });
+        let mut buf = [0u8; 8192];
         loop {
             match reader.read(&mut buf) {
                 Ok(0) => break,
                 Ok(n) => {
-                    if let Ok(s) = std::str::from_utf8(&buf[..n]) {
-                        let _ = app_handle.emit("term-data", s.to_string());
+                    if chunk_tx.send(buf[..n].to_vec()).is_err() {
+                        break;
                     }
                 }
                 Err(_) => break,
             }
         }
+        drop(chunk_tx);
+        let _ = emitter.join();
     });
 
-    let session = PtySession {
+    let exited = Arc::new(OnceLock::new());
+    let watcher_exited = exited.clone();
+    let watcher_handle = thread::spawn(move || {
+        let status = child
+            .wait()
+            .unwrap_or_else(|_| ExitStatus::with_exit_code(1));
+        let _ = watcher_exited.set(Instant::now());
+        let _ = drained_rx.recv_timeout(DRAIN_GRACE);
+        on_exit(&status);
+    });
+
+    Ok(PtySession {
+        options,
         master,
-        writer: Arc::new(Mutex::new(writer)),
-        child: Some(child),
+        input: Arc::new(Input::new(writer)),
+        killer,
+        pid,
+        scrollback,
+        recording,
+        exited,
         reader_handle: Some(reader_handle),
-    };
+        watcher_handle: Some(watcher_handle),
+    })
+}
 
-    let mut guard = state
-        .pty
-        .lock()
-        .map_err(|_| "pty mutex poisoned".to_string())?;
-    *guard = Some(session);
-    Ok(1)
+/// Turns raw PTY reads into text for `on_output`. Chunks arriving within
+/// `COALESCE_WINDOW` of each other go out as one call, so heavy output becomes a few
+/// events instead of one per read, and characters split across reads stay whole.
+fn coalesce_output(chunks: mpsc::Receiver<Vec<u8>>, on_output: impl Fn(&str)) {
+    let mut decoder = Utf8Decoder::default();
+    let mut open = true;
+    while open {
+        let Ok(mut batch) = chunks.recv() else {
+            break;
+        };
+        let deadline = Instant::now() + COALESCE_WINDOW;
+        while batch.len() < COALESCE_LIMIT {
+            match chunks.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(chunk) => batch.extend_from_slice(&chunk),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    open = false;
+                    break;
+                }
+            }
+        }
+        let text = decoder.decode(&batch);
+        if !text.is_empty() {
+            on_output(&text);
+        }
+    }
+    let tail = decoder.finish();
+    if !tail.is_empty() {
+        on_output(&tail);
+    }
+}
+
+/// Starts `options` as terminal `id`, emitting its output and exit as `term-data` and
+/// `term-exit`. A shell that exits on its own leaves its session registered, marked
+/// exited, for `EXITED_TTL`, after which it is reaped unless it was closed or restarted
+/// first. Reaping goes by how long ago a session exited, so an exit that comes before the
+/// caller registers the session still has it reaped.
+fn start_pty(
+    app: &tauri::AppHandle,
+    id: u64,
+    options: TerminalOptions,
+    recording: Arc<RecordingSlot>,
+) -> Result<PtySession, String> {
+    let output_handle = app.clone();
+    let exit_handle = app.clone();
+    start_session(
+        options,
+        recording,
+        move |data, seq| {
+            let _ = output_handle.emit(
+                "term-data",
+                TerminalData {
+                    id,
+                    seq,
+                    data: data.to_string(),
+                },
+            );
+        },
+        move |status| {
+            let _ = exit_handle.emit(
+                "term-exit",
+                TerminalExit {
+                    id,
+                    exit_code: status.exit_code(),
+                    success: status.success(),
+                },
+            );
+            thread::spawn(move || {
+                thread::sleep(EXITED_TTL);
+                if let Some(state) = exit_handle.try_state::<AppState>() {
+                    state.reap_exited(EXITED_TTL);
+                }
+            });
+        },
+    )
 }
 
 #[tauri::command]
 pub fn spawn_terminal(
     app: tauri::AppHandle,
     state: tauri::State<'_, AppState>,
+    cwd: Option<String>,
+    shell: Option<String>,
+    args: Option<Vec<String>>,
+    env: Option<HashMap<String, String>>,
 ) -> Result<u64, String> {
-    spawn_pty(&app, &state)
+    let cwd = match cwd {
+        Some(cwd) => Some(cwd),
+        None => state
+            .workspace_root
+            .lock()
+            .map_err(|_| "workspace mutex poisoned".to_string())?
+            .as_ref()
+            .map(|root| root.to_string_lossy().into_owned()),
+    };
+    let options = TerminalOptions {
+        cwd,
+        shell,
+        args: args.unwrap_or_default(),
+        env: env.unwrap_or_default(),
+    };
+    let id = state.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+    state.insert(id, start_pty(&app, id, options, Arc::default())?)?;
+    Ok(id)
 }
 
 #[tauri::command]
-pub fn write_to_terminal(data: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
-    let guard = state
-        .pty
-        .lock()
-        .map_err(|_| "pty mutex poisoned".to_string())?;
-    if let Some(session) = guard.as_ref() {
-        let mut writer = session
-            .writer
+pub fn write_to_terminal(
+    id: u64,
+    data: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    state.with_session(id, |session| session.input(id)?.keys(data.as_bytes()))
+}
+
+/// Pastes `text` into terminal `id`, bracketed when the program in it has asked for
+/// bracketed paste. The write happens off the main thread, so a large context bundle does
+/// not freeze the UI while the program reads it; typing during it goes in after the paste,
+/// except that `^C`, `^Z` or `^\` cuts the paste short at the next chunk.
+#[tauri::command]
+pub async fn paste_to_terminal(
+    id: u64,
+    text: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    let (input, bracketed) = state.with_session(id, |session| {
+        let bracketed = session
+            .scrollback
             .lock()
-            .map_err(|_| "writer mutex poisoned".to_string())?;
-        writer
-            .write_all(data.as_bytes())
-            .map_err(|e| format!("write failed: {e}"))?;
-        writer.flush().ok();
-        Ok(())
-    } else {
-        Err("terminal not spawned".into())
-    }
+            .map_err(|_| "scrollback mutex poisoned".to_string())?
+            .paste_mode
+            .bracketed;
+        Ok((session.input(id)?.clone(), bracketed))
+    })?;
+    let payload = paste_payload(&text, bracketed);
+    tauri::async_runtime::spawn_blocking(move || input.paste(payload.as_bytes()))
+        .await
+        .map_err(|e| format!("paste failed: {e}"))?
 }
 
 #[tauri::command]
 pub fn resize_terminal(
+    id: u64,
     cols: u16,
     rows: u16,
     state: tauri::State<'_, AppState>,
 ) -> Result<(), String> {
-    let guard = state
-        .pty
-        .lock()
-        .map_err(|_| "pty mutex poisoned".to_string())?;
-    if let Some(session) = guard.as_ref() {
+    state.with_session(id, |session| {
         let size = PtySize {
             cols,
             rows,
             pixel_width: 0,
             pixel_height: 0,
         };
-        if let Ok(master) = session.master.lock() {
-            master
-                .resize(size)
-                .map_err(|e| format!("resize failed: {e}"))?;
+        let master = session
+            .master
+            .lock()
+            .map_err(|_| "master mutex poisoned".to_string())?;
+        master
+            .resize(size)
+            .map_err(|e| format!("resize failed: {e}"))?;
+        session.recording.resize(cols, rows);
+        Ok(())
+    })
+}
+
+/// Starts recording terminal `id` to an asciicast file under the opened folder and
+/// returns its path.
+#[tauri::command]
+pub fn start_terminal_recording(
+    id: u64,
+    state: tauri::State<'_, AppState>,
+) -> Result<String, String> {
+    let root = state
+        .workspace_root
+        .lock()
+        .map_err(|_| "workspace mutex poisoned".to_string())?
+        .clone()
+        .ok_or_else(|| "open a folder to record terminals".to_string())?;
+    state.with_session(id, |session| {
+        let size = session
+            .master
+            .lock()
+            .map_err(|_| "master mutex poisoned".to_string())?
+            .get_size()
+            .map_err(|e| format!("size failed: {e}"))?;
+        let title = std::iter::once(session.options.shell.clone().unwrap_or_else(default_shell))
+            .chain(session.options.args.iter().cloned())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let recorder = Recorder::create(
+            &root,
+            &format!("terminal-{id}"),
+            &title,
+            size.cols,
+            size.rows,
+        )?;
+        let path = session.recording.start(recorder)?;
+        Ok(path.to_string_lossy().into_owned())
+    })
+}
+
+/// Stops recording terminal `id`, returning the finished file if it was recording.
+#[tauri::command]
+pub fn stop_terminal_recording(
+    id: u64,
+    state: tauri::State<'_, AppState>,
+) -> Result<Option<String>, String> {
+    state.with_session(id, |session| {
+        Ok(session
+            .recording
+            .stop()
+            .map(|path| path.to_string_lossy().into_owned()))
+    })
+}
+
+/// Returns terminal `id`'s scrollback and size so a reloaded view can redraw it, then
+/// apply only `term-data` events with a higher `seq`. An exited terminal keeps its
+/// scrollback until it is reaped; a restarted one starts over from an empty scrollback
+/// and `seq` 0.
+#[tauri::command]
+pub fn get_terminal_snapshot(
+    id: u64,
+    state: tauri::State<'_, AppState>,
+) -> Result<TerminalSnapshot, String> {
+    state.with_session(id, |session| session.snapshot(id))
+}
+
+/// Kills the terminal's shell, reaps it, and joins its reader thread. Runs off the main
+/// thread since the reader may take a moment to see the PTY close.
+#[tauri::command]
+pub async fn close_terminal(id: u64, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    let session = state.take_session(id)?;
+    tauri::async_runtime::spawn_blocking(move || session.close())
+        .await
+        .map_err(|e| format!("close failed: {e}"))
+}
+
+/// Replaces terminal `id`, whether its shell is running or has exited, with a fresh shell
+/// started from the same options, keeping the id so the UI can reuse its tab. The old
+/// shell's `term-exit` is emitted first, and a recording in progress carries on into the
+/// new shell.
+#[tauri::command]
+pub async fn restart_terminal(id: u64, app: tauri::AppHandle) -> Result<(), String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        app.state::<AppState>().restart(id, |options, recording| {
+            start_pty(&app, id, options, recording)
+        })
+    })
+    .await
+    .map_err(|e| format!("restart failed: {e}"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decoder_carries_split_characters_between_reads() {
+        let text = "box ─ emoji 🦀 cjk 漢字";
+        for split in 0..=text.len() {
+            let mut decoder = Utf8Decoder::default();
+            let (head, tail) = text.as_bytes().split_at(split);
+            let decoded = decoder.decode(head) + &decoder.decode(tail) + &decoder.finish();
+            assert_eq!(decoded, text, "split at {split}");
+        }
+
+        let mut decoder = Utf8Decoder::default();
+        assert_eq!(decoder.decode(b"a\xffb\xe2\x94"), "a\u{fffd}b");
+        assert_eq!(decoder.finish(), "\u{fffd}");
+    }
+
+    #[test]
+    fn output_buffer_drops_whole_characters_from_the_front() {
+        let mut buffer = OutputBuffer::new(Some(5));
+        buffer.push("ab");
+        buffer.push("éé");
+        assert_eq!(buffer.text(), "béé");
+        assert!(buffer.truncated);
+        buffer.push("c");
+        assert_eq!(buffer.text(), "ééc");
+        buffer.push("x");
+        assert_eq!(buffer.text(), "écx");
+
+        let mut buffer = OutputBuffer::new(Some(4));
+        buffer.push("日本語");
+        assert_eq!(buffer.text(), "語");
+        assert!(buffer.truncated);
+
+        let mut buffer = OutputBuffer::new(None);
+        buffer.push("unbounded");
+        assert!(!buffer.truncated);
+    }
+
+    #[test]
+    fn coalesced_output_keeps_characters_split_across_reads() {
+        let text = "┌─┐ 🦀 漢字";
+        let (tx, rx) = mpsc::channel();
+        for byte in text.as_bytes() {
+            tx.send(vec![*byte]).unwrap();
+        }
+        tx.send(b"\xff".to_vec()).unwrap();
+        drop(tx);
+
+        let calls = Mutex::new(Vec::new());
+        coalesce_output(rx, |data| calls.lock().unwrap().push(data.to_string()));
+        assert_eq!(calls.into_inner().unwrap(), [format!("{text}\u{fffd}")]);
+    }
+
+    #[test]
+    fn paste_mode_follows_switches_split_across_chunks() {
+        let mut mode = PasteMode::default();
+        mode.observe("prompt \x1b[?20");
+        assert!(!mode.bracketed);
+        mode.observe("04h$ ");
+        assert!(mode.bracketed);
+        mode.observe("ls\r\n\x1b[?2004l\r\nfile ─ 漢字\r\n");
+        assert!(!mode.bracketed);
+        mode.observe("\x1b[?2004l\x1b[?2004h$ ");
+        assert!(mode.bracketed);
+    }
+
+    #[test]
+    fn pastes_are_bracketed_only_when_asked_for() {
+        assert_eq!(paste_payload("a\nb\r\nc", false), "a\rb\rc");
+        assert_eq!(
+            paste_payload("rm -rf x\x1b[201~\nls", true),
+            "\x1b[200~rm -rf x\rls\x1b[201~"
+        );
+    }
+
+    /// Records what is written to it, stalling the first write until released, like a
+    /// PTY whose program is slow to read.
+    struct StalledWriter {
+        out: Arc<Mutex<Vec<u8>>>,
+        stall: Option<(mpsc::Sender<()>, mpsc::Receiver<()>)>,
+    }
+
+    impl Write for StalledWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if let Some((stalled, release)) = self.stall.take() {
+                let _ = stalled.send(());
+                let _ = release.recv();
+            }
+            self.out.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn keystrokes_and_other_pastes_wait_for_the_paste_in_progress() {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let (stalled_tx, stalled_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel();
+        let input = Arc::new(Input::new(Box::new(StalledWriter {
+            out: out.clone(),
+            stall: Some((stalled_tx, release_rx)),
+        })));
+        let first = paste_payload("echo one\necho two", true);
+        let second = paste_payload("echo three", true);
+
+        let paster = input.clone();
+        let payload = first.clone();
+        let first_paste = thread::spawn(move || paster.paste(payload.as_bytes()));
+        stalled_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        input.keys(b"ls\r").unwrap();
+        let paster = input.clone();
+        let payload = second.clone();
+        let second_paste = thread::spawn(move || paster.paste(payload.as_bytes()));
+        thread::sleep(Duration::from_millis(20));
+        input.keys(b"pwd\r").unwrap();
+
+        release_tx.send(()).unwrap();
+        first_paste.join().unwrap().unwrap();
+        second_paste.join().unwrap().unwrap();
+        input.keys(b"q").unwrap();
+        let out = String::from_utf8(out.lock().unwrap().clone()).unwrap();
+        assert_eq!(out, format!("{first}ls\rpwd\r{second}q"));
+    }
+
+    #[test]
+    fn interrupts_cut_a_stalled_paste_short_instead_of_queueing_behind_it() {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let (stalled_tx, stalled_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel();
+        let input = Arc::new(Input::new(Box::new(StalledWriter {
+            out: out.clone(),
+            stall: Some((stalled_tx, release_rx)),
+        })));
+        let payload = paste_payload(&"x".repeat(3 * PASTE_CHUNK), true);
+
+        let paster = input.clone();
+        let bytes = payload.clone();
+        let paste = thread::spawn(move || paster.paste(bytes.as_bytes()));
+        stalled_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        // None of these wait for the stalled reader.
+        input.keys(b"ls").unwrap();
+        input.keys(b"\x03").unwrap();
+        input.keys(b"pwd\r").unwrap();
+
+        release_tx.send(()).unwrap();
+        paste.join().unwrap().unwrap();
+        let out = String::from_utf8(out.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            out,
+            format!("{}{PASTE_END}\x03pwd\r", &payload[..PASTE_CHUNK])
+        );
+    }
+
+    fn sh(script: &str) -> TerminalOptions {
+        TerminalOptions {
+            shell: Some("/bin/sh".to_string()),
+            args: vec!["-c".to_string(), script.to_string()],
+            ..TerminalOptions::default()
         }
-        return Ok(());
     }
-    Err("terminal not spawned".into())
+
+    #[cfg(unix)]
+    #[test]
+    fn exit_is_reported_after_the_last_output_and_kept_in_the_scrollback() {
+        let (tx, rx) = mpsc::channel();
+        let output_tx = tx.clone();
+        let session = start_session(
+            sh("echo bye; exit 3"),
+            Arc::default(),
+            move |data, _| {
+                let _ = output_tx.send(Ok(data.to_string()));
+            },
+            move |status| {
+                let _ = tx.send(Err(status.exit_code()));
+            },
+        )
+        .unwrap();
+
+        let mut output = String::new();
+        let code = loop {
+            match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+                Ok(data) => output.push_str(&data),
+                Err(code) => break code,
+            }
+        };
+        assert!(output.contains("bye"), "{output:?}");
+        assert_eq!(code, 3);
+        assert!(session.exited.get().is_some());
+
+        let snapshot = session.snapshot(7).unwrap();
+        assert_eq!(snapshot.data, output);
+        assert!(snapshot.seq >= 1);
+        assert_eq!((snapshot.cols, snapshot.rows), (80, 24));
+        session.close();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exited_terminals_keep_their_scrollback_until_restarted() {
+        let state = AppState::default();
+        let (exit_tx, exit_rx) = mpsc::channel();
+        let start = |options: TerminalOptions, recording: Arc<RecordingSlot>| {
+            let exit_tx = exit_tx.clone();
+            start_session(
+                options,
+                recording,
+                |_, _| {},
+                move |status| {
+                    let _ = exit_tx.send(status.exit_code());
+                },
+            )
+        };
+        state
+            .insert(
+                1,
+                start(sh("echo started; exit 2"), Arc::default()).unwrap(),
+            )
+            .unwrap();
+        assert_eq!(exit_rx.recv_timeout(Duration::from_secs(5)), Ok(2));
+
+        let snapshot = state
+            .with_session(1, |session| session.snapshot(1))
+            .unwrap();
+        assert!(snapshot.data.contains("started"), "{:?}", snapshot.data);
+        let err = state
+            .with_session(1, |session| session.input(1).map(drop))
+            .unwrap_err();
+        assert!(err.contains("exited"), "{err}");
+
+        state.restart(1, start).unwrap();
+        assert_eq!(exit_rx.recv_timeout(Duration::from_secs(5)), Ok(2));
+        let snapshot = state
+            .with_session(1, |session| session.snapshot(1))
+            .unwrap();
+        assert_eq!(
+            snapshot.data.matches("started").count(),
+            1,
+            "{:?}",
+            snapshot.data
+        );
+        state.close_all();
+        assert!(state.with_session(1, |_| Ok(())).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exited_terminals_are_reaped_once_their_grace_period_passes() {
+        let state = AppState::default();
+        let (exit_tx, exit_rx) = mpsc::channel();
+        let exited = start_session(
+            sh("exit 0"),
+            Arc::default(),
+            |_, _| {},
+            move |_| {
+                let _ = exit_tx.send(());
+            },
+        )
+        .unwrap();
+        state.insert(1, exited).unwrap();
+        let running = start_session(sh("sleep 30"), Arc::default(), |_, _| {}, |_| {}).unwrap();
+        state.insert(2, running).unwrap();
+        exit_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        state.reap_exited(Duration::from_secs(60));
+        assert!(state.with_session(1, |_| Ok(())).is_ok());
+        state.reap_exited(Duration::ZERO);
+        assert!(state.with_session(1, |_| Ok(())).is_err());
+        assert!(state.with_session(2, |_| Ok(())).is_ok());
+        state.close_all();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn close_reaps_the_shell_and_joins_the_reader() {
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let (exit_tx, exit_rx) = mpsc::channel();
+        let session = start_session(
+            sh("trap '' HUP; sleep 30 & echo ready; wait"),
+            Arc::default(),
+            move |_, _| {
+                let _ = ready_tx.send(());
+            },
+            move |status| {
+                let _ = exit_tx.send(status.success());
+            },
+        )
+        .unwrap();
+        ready_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let started = std::time::Instant::now();
+        session.close();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(exit_rx.try_recv(), Ok(false));
+    }
 }
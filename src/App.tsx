@@ -1,4 +1,4 @@
-import { useEffect, useMemo, useState } from "react";
+import { useEffect, useMemo, useRef, useState } from "react";
 import { listen } from "@tauri-apps/api/event";
 import { invoke } from "@tauri-apps/api/core";
 import { open } from "@tauri-apps/plugin-dialog";
@@ -17,6 +17,8 @@ type AgentSummary = {
 type SessionInfo = {
   agent_id: string;
   session_id: string;
+  root_dir: string;
+  staged_writes: boolean;
 };
 
 type ChatEntry = {
@@ -26,12 +28,249 @@ type ChatEntry = {
   streaming?: boolean;
 };
 
-type AcpUpdateEvent = {
-  type: "chat_message" | "status_update" | "error";
-  session_id: string;
+type PromptTicket = {
+  prompt_id: string;
+  queue_position: number;
+};
+
+const stopReasonMessages: Record<string, string> = {
+  max_tokens: "Turn ended: the agent hit its token limit.",
+  max_turn_requests: "Turn ended: the agent hit its request limit.",
+  refusal: "Turn ended: the agent refused to continue.",
+  cancelled: "Prompt cancelled.",
+};
+
+type UiContent =
+  | { type: "text"; text: string }
+  | { type: "image"; mime_type: string; data: string; uri: string | null }
+  | { type: "audio"; mime_type: string; data: string }
+  | {
+      type: "resource_link";
+      uri: string;
+      name: string;
+      title: string | null;
+      description: string | null;
+      mime_type: string | null;
+      size: number | null;
+    }
+  | {
+      type: "resource";
+      uri: string;
+      mime_type: string | null;
+      text: string | null;
+      blob: string | null;
+    }
+  | { type: "unsupported" };
+
+type UiToolCallContent =
+  | { type: "content"; content: UiContent }
+  | { type: "diff"; path: string; old_text: string | null; new_text: string }
+  | { type: "terminal"; terminal_id: string };
+
+type UiLocation = { path: string; line: number | null };
+
+type ToolCallStatus = "pending" | "in_progress" | "completed" | "failed";
+
+type UiToolCall = {
+  tool_call_id: string;
+  title: string;
+  kind: string;
+  status: ToolCallStatus;
+  content: UiToolCallContent[];
+  locations: UiLocation[];
+  raw_input: unknown;
+  raw_output: unknown;
+};
+
+type UiDiff = { path: string; old_text: string | null; new_text: string };
+
+type UiTrackedToolCall = UiToolCall & {
+  diffs: UiDiff[];
+  touched_paths: string[];
+};
+
+type UiFileChange = {
+  change_id: number;
+  path: string;
+  previous_content: string | null;
+  new_content: string;
+  tool_call_id: string | null;
+  prompt_id: string | null;
+  timestamp_ms: number;
+};
+
+type UiStagedFile = {
+  path: string;
+  created: boolean;
+  tool_call_id: string | null;
+  prompt_id: string | null;
+  hunks: {
+    index: number;
+    old_start: number;
+    old_lines: number;
+    new_start: number;
+    new_lines: number;
+    removed: string;
+    added: string;
+  }[];
+};
+
+type UiToolCallUpdate = {
+  tool_call_id: string;
+  title: string | null;
+  kind: string | null;
+  status: ToolCallStatus | null;
+  content: UiToolCallContent[] | null;
+  locations: UiLocation[] | null;
+  raw_input: unknown;
+  raw_output: unknown;
+};
+
+type UiPlanEntry = {
   content: string;
+  priority: "high" | "medium" | "low";
+  status: "pending" | "in_progress" | "completed";
+};
+
+type UiCommand = {
+  name: string;
+  description: string;
+  input_hint: string | null;
+};
+
+type PermissionOption = {
+  option_id: string;
+  name: string;
+  kind: "allow_once" | "allow_always" | "reject_once" | "reject_always";
+};
+
+type PermissionPrompt = {
+  requestId: string;
+  title: string;
+  options: PermissionOption[];
+};
+
+type AcpUpdateEvent =
+  | {
+      type: "agent_message" | "agent_thought" | "user_message";
+      session_id: string;
+      content: UiContent;
+    }
+  | { type: "tool_call"; session_id: string; tool_call: UiTrackedToolCall }
+  | {
+      type: "tool_call_update";
+      session_id: string;
+      update: UiToolCallUpdate;
+      tool_call: UiTrackedToolCall;
+    }
+  | { type: "file_changed"; session_id: string; change: UiFileChange }
+  | { type: "rolled_back"; session_id: string; paths: string[] }
+  | {
+      type: "staged";
+      session_id: string;
+      path: string;
+      file: UiStagedFile | null;
+    }
+  | {
+      type: "terminal_started";
+      session_id: string;
+      terminal_id: string;
+      command: string;
+    }
+  | {
+      type: "command_denied";
+      session_id: string;
+      command: string;
+      message: string;
+    }
+  | {
+      type: "terminal_output";
+      session_id: string;
+      terminal_id: string;
+      data: string;
+    }
+  | {
+      type: "terminal_exited";
+      session_id: string;
+      terminal_id: string;
+      exit_code: number | null;
+      signal: string | null;
+      timed_out: boolean;
+    }
+  | { type: "plan"; session_id: string; entries: UiPlanEntry[] }
+  | { type: "available_commands"; session_id: string; commands: UiCommand[] }
+  | { type: "current_mode"; session_id: string; mode_id: string }
+  | {
+      type: "status_update" | "error";
+      session_id: string;
+      content: string;
+    }
+  | {
+      type: "permission_request";
+      session_id: string;
+      request_id: string;
+      tool_call: UiToolCallUpdate;
+      options: PermissionOption[];
+    }
+  | {
+      type: "prompt_queued";
+      session_id: string;
+      prompt_id: string;
+      position: number;
+    }
+  | { type: "turn_started"; session_id: string; prompt_id: string }
+  | {
+      type: "turn_ended";
+      session_id: string;
+      prompt_id: string;
+      stop_reason:
+        | "end_turn"
+        | "max_tokens"
+        | "max_turn_requests"
+        | "refusal"
+        | "cancelled";
+    }
+  | {
+      type: "turn_failed";
+      session_id: string;
+      prompt_id: string;
+      content: string;
+    }
+  | {
+      type: "permission_resolved";
+      session_id: string;
+      request_id: string;
+      option_id: string | null;
+    };
+
+type AgentTerminal = {
+  terminalId: string;
+  command: string;
+  output: string;
+  exitCode: number | null | undefined;
+  exitLabel?: string;
 };
 
+// Agent terminals are shown as plain text, so drop ANSI escape sequences.
+const ansiPattern = /\x1b\[[0-9;?]*[ -/]*[@-~]|\x1b\][^\x07]*(\x07|\x1b\\)|\r(?!\n)/g;
+
+function stripAnsi(text: string) {
+  return text.replace(ansiPattern, "").replace(/\r\n/g, "\n");
+}
+
+function contentToText(content: UiContent): string | null {
+  switch (content.type) {
+    case "text":
+      return content.text;
+    case "resource_link":
+      return content.uri;
+    case "resource":
+      return content.text ?? content.uri;
+    default:
+      return null;
+  }
+}
+
 const initialPinned: PinnedItem[] = [
   { id: "1", label: "core/app.ts", path: "core/app.ts" },
   { id: "2", label: "ui/map/canvas.tsx", path: "ui/map/canvas.tsx" },
@@ -41,6 +280,7 @@ const initialPinned: PinnedItem[] = [
 const tabs = [
   { id: "codemap", label: "Codemap" },
   { id: "files", label: "Files" },
+  { id: "terminal", label: "Terminal" },
   { id: "preview", label: "Preview" },
 ] as const;
 
@@ -62,11 +302,29 @@ function App() {
   const [agents, setAgents] = useState<AgentSummary[]>([]);
   const [selectedAgentId, setSelectedAgentId] = useState<string | null>(null);
   const [sessionId, setSessionId] = useState<string | null>(null);
+  const sessionIdRef = useRef<string | null>(null);
+  const [editorFile, setEditorFile] = useState<string | null>(null);
+  const [editorContent, setEditorContent] = useState("");
+  const [editorDirty, setEditorDirty] = useState(false);
+  const editorFileRef = useRef<string | null>(null);
   const [sessionStatus, setSessionStatus] = useState<
     "idle" | "starting" | "active" | "error"
   >("idle");
   const [chatEntries, setChatEntries] = useState<ChatEntry[]>([]);
   const [composerText, setComposerText] = useState("");
+  const [pendingPrompts, setPendingPrompts] = useState<string[]>([]);
+  const [touchedPaths, setTouchedPaths] = useState<Set<string>>(new Set());
+  const [lastTurnId, setLastTurnId] = useState<string | null>(null);
+  const [stageWrites, setStageWrites] = useState(false);
+  const [stagedFiles, setStagedFiles] = useState<UiStagedFile[]>([]);
+  const [agentTerminals, setAgentTerminals] = useState<AgentTerminal[]>([]);
+  const [recordingTerminals, setRecordingTerminals] = useState(false);
+  const [terminalInputs, setTerminalInputs] = useState<Record<string, string>>(
+    {},
+  );
+  const [permissionPrompts, setPermissionPrompts] = useState<
+    PermissionPrompt[]
+  >([]);
 
   useEffect(() => {
     const unlistenPromise = listen("menu://open-folder", () => {
@@ -77,6 +335,22 @@ function App() {
     };
   }, []);
 
+  useEffect(() => {
+    const unlistenPromise = listen<{ path: string; content: string }>(
+      "editor://buffer-updated",
+      (event) => {
+        const file = editorFileRef.current;
+        if (file && event.payload.path.endsWith(`/${file}`)) {
+          setEditorContent(event.payload.content);
+          appendStatus(`Agent updated unsaved ${file}.`);
+        }
+      },
+    );
+    return () => {
+      unlistenPromise.then((unlisten) => unlisten());
+    };
+  }, []);
+
   useEffect(() => {
     void invoke<AgentSummary[]>("acp_list_agents")
       .then((list) => {
@@ -91,12 +365,135 @@ function App() {
   useEffect(() => {
     const unlistenPromise = listen<AcpUpdateEvent>("acp://update", (event) => {
       const payload = event.payload;
-      if (payload.type === "chat_message") {
-        appendAssistantChunk(payload.content);
+      if (payload.session_id !== sessionIdRef.current) {
+        return;
+      }
+      if (payload.type === "tool_call" || payload.type === "tool_call_update") {
+        const paths = payload.tool_call.touched_paths;
+        setTouchedPaths((prev) =>
+          paths.every((path) => prev.has(path))
+            ? prev
+            : new Set([...prev, ...paths]),
+        );
+      }
+      if (payload.type === "agent_message") {
+        const text = contentToText(payload.content);
+        if (text) appendAssistantChunk(text);
+      } else if (payload.type === "tool_call") {
+        appendStatus(
+          `${payload.tool_call.title} (${payload.tool_call.status})`,
+        );
+      } else if (
+        payload.type === "tool_call_update" &&
+        payload.update.status &&
+        payload.update.status !== "in_progress"
+      ) {
+        appendStatus(
+          `${payload.update.title ?? "Tool call"} (${payload.update.status})`,
+        );
+      } else if (payload.type === "file_changed") {
+        const { path, previous_content } = payload.change;
+        setTouchedPaths((prev) =>
+          prev.has(path) ? prev : new Set([...prev, path]),
+        );
+        appendStatus(
+          `${previous_content === null ? "Created" : "Updated"} ${path}`,
+        );
+      } else if (payload.type === "rolled_back") {
+        appendStatus(
+          payload.paths.length > 0
+            ? `Rolled back ${payload.paths.join(", ")}`
+            : "Nothing to roll back.",
+        );
+      } else if (payload.type === "staged") {
+        const { path, file } = payload;
+        setStagedFiles((prev) => {
+          const rest = prev.filter((staged) => staged.path !== path);
+          return file ? [...rest, file] : rest;
+        });
+      } else if (payload.type === "terminal_started") {
+        setAgentTerminals((prev) => [
+          ...prev,
+          {
+            terminalId: payload.terminal_id,
+            command: payload.command,
+            output: "",
+            exitCode: undefined,
+          },
+        ]);
+        appendStatus(`Running ${payload.command}`);
+      } else if (payload.type === "command_denied") {
+        appendStatus(`Blocked command: ${payload.message}`);
+      } else if (payload.type === "terminal_output") {
+        setAgentTerminals((prev) =>
+          prev.map((terminal) =>
+            terminal.terminalId === payload.terminal_id
+              ? { ...terminal, output: terminal.output + payload.data }
+              : terminal,
+          ),
+        );
+      } else if (payload.type === "terminal_exited") {
+        setAgentTerminals((prev) =>
+          prev.map((terminal) =>
+            terminal.terminalId === payload.terminal_id
+              ? {
+                  ...terminal,
+                  exitCode: payload.exit_code,
+                  exitLabel: payload.timed_out
+                    ? "timed out"
+                    : payload.signal
+                      ? `killed (${payload.signal})`
+                      : `exited ${payload.exit_code ?? "?"}`,
+                }
+              : terminal,
+          ),
+        );
+        if (payload.timed_out) {
+          appendStatus(`Terminal ${payload.terminal_id} timed out and was killed.`);
+        }
+      } else if (payload.type === "plan") {
+        appendStatus(
+          `Plan: ${payload.entries
+            .map((entry) => `[${entry.status}] ${entry.content}`)
+            .join(" · ")}`,
+        );
+      } else if (payload.type === "current_mode") {
+        appendStatus(`Mode: ${payload.mode_id}`);
       } else if (payload.type === "status_update") {
         appendStatus(payload.content);
       } else if (payload.type === "error") {
         appendStatus(`Error: ${payload.content}`);
+      } else if (payload.type === "permission_request") {
+        setPermissionPrompts((prev) => [
+          ...prev,
+          {
+            requestId: payload.request_id,
+            title: payload.tool_call.title ?? payload.tool_call.tool_call_id,
+            options: payload.options,
+          },
+        ]);
+      } else if (payload.type === "prompt_queued") {
+        appendStatus(`Prompt queued (position ${payload.position}).`);
+      } else if (
+        payload.type === "turn_ended" ||
+        payload.type === "turn_failed"
+      ) {
+        setPendingPrompts((prev) =>
+          prev.filter((promptId) => promptId !== payload.prompt_id),
+        );
+        setLastTurnId(payload.prompt_id);
+        if (payload.type === "turn_failed") {
+          appendStatus(`Error: ${payload.content}`);
+        } else if (stopReasonMessages[payload.stop_reason]) {
+          appendStatus(stopReasonMessages[payload.stop_reason]);
+        }
+      } else if (payload.type === "permission_resolved") {
+        setPermissionPrompts((prev) =>
+          prev.filter((prompt) => prompt.requestId !== payload.request_id),
+        );
+        if (payload.option_id === null) {
+          appendStatus("Permission request cancelled.");
+        }
       }
     });
 
@@ -105,6 +502,10 @@ function App() {
     };
   }, []);
 
+  useEffect(() => {
+    sessionIdRef.current = sessionId;
+  }, [sessionId]);
+
   const stats = useMemo(
     () => [
       { label: "Session", value: sessionStatus },
@@ -202,7 +603,9 @@ function App() {
       const info = await invoke<SessionInfo>("acp_start_session", {
         agent_id: selectedAgentId,
         root_dir: openPath,
+        staged_writes: stageWrites,
       });
+      sessionIdRef.current = info.session_id;
       setSessionId(info.session_id);
       setSessionStatus("active");
       appendStatus(`Session started (${info.agent_id}).`);
@@ -213,10 +616,63 @@ function App() {
     }
   };
 
+  const handleTerminalInput = async (terminalId: string) => {
+    if (!sessionId) return;
+    const line = terminalInputs[terminalId] ?? "";
+    try {
+      await invoke("acp_write_terminal", {
+        session_id: sessionId,
+        terminal_id: terminalId,
+        data: `${line}\r`,
+      });
+      setTerminalInputs((prev) => ({ ...prev, [terminalId]: "" }));
+    } catch (err) {
+      appendStatus(`Failed to write to terminal: ${err}`);
+    }
+  };
+
+  const handleTerminalKill = async (terminalId: string) => {
+    if (!sessionId) return;
+    try {
+      await invoke("acp_kill_terminal", {
+        session_id: sessionId,
+        terminal_id: terminalId,
+      });
+    } catch (err) {
+      appendStatus(`Failed to kill terminal: ${err}`);
+    }
+  };
+
+  const handleToggleRecording = async () => {
+    if (!sessionId) return;
+    try {
+      if (recordingTerminals) {
+        const paths = await invoke<string[]>("acp_stop_recording", {
+          session_id: sessionId,
+        });
+        setRecordingTerminals(false);
+        paths.forEach((path) => appendStatus(`Saved recording ${path}`));
+      } else {
+        await invoke("acp_start_recording", { session_id: sessionId });
+        setRecordingTerminals(true);
+      }
+    } catch (err) {
+      appendStatus(`Failed to toggle terminal recording: ${err}`);
+    }
+  };
+
   const handleStopSession = async () => {
+    if (!sessionId) return;
     try {
-      await invoke("acp_stop_session");
+      await invoke("acp_stop_session", { session_id: sessionId });
       setSessionId(null);
+      setPermissionPrompts([]);
+      setPendingPrompts([]);
+      setTouchedPaths(new Set());
+      setLastTurnId(null);
+      setStagedFiles([]);
+      setAgentTerminals([]);
+      setRecordingTerminals(false);
       setSessionStatus("idle");
       appendStatus("Session stopped.");
     } catch (err) {
@@ -240,13 +696,114 @@ function App() {
     setComposerText("");
 
     try {
-      await invoke("acp_send_prompt", { text });
+      const ticket = await invoke<PromptTicket>("acp_send_prompt", {
+        session_id: sessionId,
+        text,
+      });
+      setPendingPrompts((prev) => [...prev, ticket.prompt_id]);
     } catch (err) {
       console.error("Failed to send prompt", err);
       appendStatus("Prompt failed to send.");
     }
   };
 
+  const handleCancelPrompt = async () => {
+    if (!sessionId) return;
+    try {
+      await invoke("acp_cancel_prompt", { session_id: sessionId });
+    } catch (err) {
+      console.error("Failed to cancel prompt", err);
+      appendStatus("Failed to cancel prompt.");
+    }
+  };
+
+  const handleUndoTurn = async () => {
+    if (!sessionId || !lastTurnId) return;
+    try {
+      await invoke("acp_rollback_turn", {
+        session_id: sessionId,
+        prompt_id: lastTurnId,
+      });
+      setLastTurnId(null);
+    } catch (err) {
+      appendStatus(`Undo failed: ${err}`);
+    }
+  };
+
+  const editorPath = (file: string) => `${openPath}/${file}`;
+
+  const handleOpenInEditor = async (file: string) => {
+    if (!openPath) return;
+    try {
+      const content = await invoke<string>("open_buffer", {
+        path: editorPath(file),
+      });
+      editorFileRef.current = file;
+      setEditorFile(file);
+      setEditorContent(content);
+      setEditorDirty(false);
+      setActiveTab("files");
+    } catch (err) {
+      appendStatus(`Failed to open ${file}: ${err}`);
+    }
+  };
+
+  const handleEditorChange = (content: string) => {
+    if (!editorFile) return;
+    setEditorContent(content);
+    setEditorDirty(true);
+    void invoke("update_buffer", {
+      path: editorPath(editorFile),
+      content,
+    }).catch((err) => console.error("Failed to update buffer", err));
+  };
+
+  const handleEditorSave = async (save: boolean) => {
+    if (!editorFile) return;
+    try {
+      await invoke(save ? "save_buffer" : "discard_buffer", {
+        path: editorPath(editorFile),
+      });
+      if (!save) {
+        setEditorContent(
+          await invoke<string>("open_buffer", { path: editorPath(editorFile) }),
+        );
+      }
+      setEditorDirty(false);
+    } catch (err) {
+      appendStatus(`Failed to ${save ? "save" : "discard"} ${editorFile}: ${err}`);
+    }
+  };
+
+  const handleStagedChoice = async (path: string, accept: boolean) => {
+    if (!sessionId) return;
+    try {
+      await invoke(accept ? "acp_accept_staged" : "acp_reject_staged", {
+        session_id: sessionId,
+        path,
+        hunk: null,
+      });
+    } catch (err) {
+      appendStatus(`Review failed: ${err}`);
+    }
+  };
+
+  const handlePermissionChoice = async (
+    requestId: string,
+    optionId: string | null,
+  ) => {
+    try {
+      await invoke("acp_respond_permission", {
+        session_id: sessionId,
+        request_id: requestId,
+        option_id: optionId,
+      });
+    } catch (err) {
+      console.error("Failed to answer permission request", err);
+      appendStatus("Failed to answer permission request.");
+    }
+  };
+
   return (
     <div className="h-screen w-screen bg-[var(--app-bg)] text-slate-100">
       <div className="relative h-full w-full bg-[radial-gradient(circle_at_top,rgba(59,130,246,0.08),transparent_55%),radial-gradient(circle_at_bottom,rgba(20,184,166,0.08),transparent_50%)]">
@@ -272,6 +829,19 @@ function App() {
                     </option>
                   ))}
                 </select>
+                {sessionStatus === "active" && (
+                  <button
+                    onClick={() => void handleToggleRecording()}
+                    title="Record the agent's terminals to .acp/recordings"
+                    className={`rounded-full border px-3 py-1 text-[11px] ${
+                      recordingTerminals
+                        ? "border-rose-400/40 bg-rose-500/20 text-rose-100 hover:bg-rose-500/30"
+                        : "border-white/10 bg-white/5 text-slate-200 hover:bg-white/10"
+                    }`}
+                  >
+                    {recordingTerminals ? "● Recording" : "Record"}
+                  </button>
+                )}
                 {sessionStatus === "active" ? (
                   <button
                     onClick={handleStopSession}
@@ -280,6 +850,16 @@ function App() {
                     Stop
                   </button>
                 ) : (
+                  <label className="flex items-center gap-1 text-[11px] text-slate-400">
+                    <input
+                      type="checkbox"
+                      checked={stageWrites}
+                      onChange={(event) => setStageWrites(event.target.checked)}
+                    />
+                    Review writes
+                  </label>
+                )}
+                {sessionStatus !== "active" && (
                   <button
                     onClick={handleStartSession}
                     className="rounded-full border border-emerald-400/40 bg-emerald-500/20 px-3 py-1 text-[11px] font-semibold text-emerald-100 hover:bg-emerald-500/30"
@@ -338,6 +918,72 @@ function App() {
               )}
             </div>
 
+            {permissionPrompts.map((prompt) => (
+              <div
+                key={prompt.requestId}
+                className="mt-4 rounded-xl border border-amber-400/30 bg-amber-500/10 p-3 text-[12px] text-amber-100"
+              >
+                <p className="font-semibold">Agent requests permission</p>
+                <p className="mt-1 text-amber-100/80">{prompt.title}</p>
+                <div className="mt-2 flex flex-wrap gap-2">
+                  {prompt.options.map((option) => (
+                    <button
+                      key={option.option_id}
+                      onClick={() =>
+                        void handlePermissionChoice(
+                          prompt.requestId,
+                          option.option_id,
+                        )
+                      }
+                      className={`rounded-full border px-3 py-1 text-[11px] font-semibold ${
+                        option.kind.startsWith("allow")
+                          ? "border-emerald-400/40 bg-emerald-500/20 text-emerald-100 hover:bg-emerald-500/30"
+                          : "border-rose-400/40 bg-rose-500/20 text-rose-100 hover:bg-rose-500/30"
+                      }`}
+                    >
+                      {option.name}
+                    </button>
+                  ))}
+                  <button
+                    onClick={() =>
+                      void handlePermissionChoice(prompt.requestId, null)
+                    }
+                    className="rounded-full border border-white/10 bg-white/5 px-3 py-1 text-[11px] text-slate-200 hover:bg-white/10"
+                  >
+                    Dismiss
+                  </button>
+                </div>
+              </div>
+            ))}
+
+            {stagedFiles.map((file) => (
+              <div
+                key={file.path}
+                className="mt-4 rounded-xl border border-sky-400/30 bg-sky-500/10 p-3 text-[12px] text-sky-100"
+              >
+                <p className="font-semibold">
+                  {file.created ? "New file" : "Staged edit"}: {file.path}
+                </p>
+                <p className="mt-1 text-sky-100/80">
+                  {file.hunks.length} hunk{file.hunks.length === 1 ? "" : "s"}
+                </p>
+                <div className="mt-2 flex flex-wrap gap-2">
+                  <button
+                    onClick={() => void handleStagedChoice(file.path, true)}
+                    className="rounded-full border border-emerald-400/40 bg-emerald-500/20 px-3 py-1 text-[11px] font-semibold text-emerald-100 hover:bg-emerald-500/30"
+                  >
+                    Accept
+                  </button>
+                  <button
+                    onClick={() => void handleStagedChoice(file.path, false)}
+                    className="rounded-full border border-rose-400/40 bg-rose-500/20 px-3 py-1 text-[11px] font-semibold text-rose-100 hover:bg-rose-500/30"
+                  >
+                    Reject
+                  </button>
+                </div>
+              </div>
+            ))}
+
             <div className="mt-4 rounded-xl border border-white/10 bg-slate-950/40 p-3">
               <div className="flex flex-wrap items-center gap-2">
                 <span className="text-[11px] uppercase tracking-[0.2em] text-slate-500">
@@ -407,11 +1053,27 @@ function App() {
                   placeholder="Ask Visor to refine the workspace..."
                   className="w-full rounded-xl border border-white/10 bg-slate-950/60 px-3 py-2 text-sm text-slate-100 placeholder:text-slate-500 focus:border-emerald-400/50 focus:outline-none"
                 />
+                {pendingPrompts.length > 0 && (
+                  <button
+                    onClick={() => void handleCancelPrompt()}
+                    className="rounded-xl border border-rose-400/40 bg-rose-500/20 px-4 py-2 text-sm font-semibold text-rose-100 hover:bg-rose-500/30"
+                  >
+                    Cancel
+                  </button>
+                )}
+                {pendingPrompts.length === 0 && lastTurnId && (
+                  <button
+                    onClick={() => void handleUndoTurn()}
+                    className="rounded-xl border border-white/10 bg-white/5 px-4 py-2 text-sm text-slate-200 hover:bg-white/10"
+                  >
+                    Undo turn
+                  </button>
+                )}
                 <button
                   onClick={() => void handleSendPrompt()}
                   className="rounded-xl bg-emerald-500 px-4 py-2 text-sm font-semibold text-slate-950 hover:bg-emerald-400"
                 >
-                  Send
+                  {pendingPrompts.length > 0 ? "Queue" : "Send"}
                 </button>
               </div>
             </div>
@@ -461,7 +1123,12 @@ function App() {
                           {codemapFiles.slice(0, 220).map((file) => (
                             <span
                               key={file}
-                              className="rounded-full border border-white/10 bg-white/5 px-3 py-1 text-[11px] text-slate-200"
+                              onClick={() => void handleOpenInEditor(file)}
+                              className={`cursor-pointer rounded-full border px-3 py-1 text-[11px] ${
+                                touchedPaths.has(file)
+                                  ? "border-amber-400/50 bg-amber-500/20 text-amber-100"
+                                  : "border-white/10 bg-white/5 text-slate-200"
+                              }`}
                             >
                               {file}
                             </span>
@@ -473,11 +1140,97 @@ function App() {
                 </div>
               )}
 
-              {activeTab !== "codemap" && (
+              {activeTab === "files" && editorFile && (
+                <div className="flex h-full flex-col gap-2">
+                  <div className="flex items-center gap-2 text-[11px] text-slate-300">
+                    <span className="truncate">
+                      {editorFile}
+                      {editorDirty ? " •" : ""}
+                    </span>
+                    <button
+                      onClick={() => void handleEditorSave(true)}
+                      disabled={!editorDirty}
+                      className="ml-auto rounded-full border border-emerald-400/40 bg-emerald-500/20 px-3 py-1 font-semibold text-emerald-100 hover:bg-emerald-500/30 disabled:opacity-40"
+                    >
+                      Save
+                    </button>
+                    <button
+                      onClick={() => void handleEditorSave(false)}
+                      disabled={!editorDirty}
+                      className="rounded-full border border-white/10 bg-white/5 px-3 py-1 text-slate-200 hover:bg-white/10 disabled:opacity-40"
+                    >
+                      Discard
+                    </button>
+                  </div>
+                  <textarea
+                    value={editorContent}
+                    onChange={(event) => handleEditorChange(event.target.value)}
+                    spellCheck={false}
+                    className="flex-1 resize-none rounded-xl border border-white/10 bg-slate-950/60 p-3 font-mono text-[12px] text-slate-100 focus:border-emerald-400/50 focus:outline-none"
+                  />
+                </div>
+              )}
+
+              {activeTab === "terminal" && agentTerminals.length > 0 && (
+                <div className="flex h-full flex-col gap-3 overflow-auto">
+                  {agentTerminals.map((terminal) => (
+                    <div
+                      key={terminal.terminalId}
+                      className="rounded-xl border border-white/10 bg-slate-950/60"
+                    >
+                      <div className="flex items-center gap-2 border-b border-white/10 px-3 py-1.5 font-mono text-[11px] text-slate-300">
+                        <span className="truncate">$ {terminal.command}</span>
+                        {terminal.exitCode !== undefined && (
+                          <span className="ml-auto text-slate-400">
+                            {terminal.exitLabel}
+                          </span>
+                        )}
+                        <button
+                          disabled={terminal.exitCode !== undefined}
+                          onClick={() =>
+                            void handleTerminalKill(terminal.terminalId)
+                          }
+                          className="ml-auto rounded-full border border-rose-400/40 bg-rose-500/20 px-2 py-0.5 text-rose-100 hover:bg-rose-500/30 disabled:hidden"
+                        >
+                          Kill
+                        </button>
+                      </div>
+                      <pre className="max-h-72 overflow-auto whitespace-pre-wrap p-3 font-mono text-[11px] text-slate-100">
+                        {stripAnsi(terminal.output)}
+                      </pre>
+                      <form
+                        onSubmit={(event) => {
+                          event.preventDefault();
+                          void handleTerminalInput(terminal.terminalId);
+                        }}
+                        className="border-t border-white/10 px-3 py-1.5"
+                      >
+                        <input
+                          value={terminalInputs[terminal.terminalId] ?? ""}
+                          onChange={(event) =>
+                            setTerminalInputs((prev) => ({
+                              ...prev,
+                              [terminal.terminalId]: event.target.value,
+                            }))
+                          }
+                          placeholder="Send input…"
+                          className="w-full bg-transparent font-mono text-[11px] text-slate-100 placeholder:text-slate-500 focus:outline-none"
+                        />
+                      </form>
+                    </div>
+                  ))}
+                </div>
+              )}
+
+              {activeTab !== "codemap" &&
+                !(activeTab === "files" && editorFile) &&
+                !(activeTab === "terminal" && agentTerminals.length > 0) && (
                 <div className="flex h-full items-center justify-center text-sm text-slate-400">
                   {activeTab === "files"
-                    ? "File explorer is coming soon."
-                    : "Preview is coming soon."}
+                    ? "Open a file from the codemap to edit it."
+                    : activeTab === "terminal"
+                      ? "Commands the agent runs will appear here."
+                      : "Preview is coming soon."}
                 </div>
               )}
             </div>